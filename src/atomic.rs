@@ -0,0 +1,146 @@
+//! Writing files so that an interruption never leaves them half written.
+
+use eyre::{eyre, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Symlinks followed before giving up, as in Linux's `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Replace the content of `target`: the content goes to a temporary file in
+/// the same directory, which is synced and then renamed over the target.
+///
+/// An existing target keeps its permissions and, where allowed, its owner.
+/// If `target` is a symlink, the file it points to is replaced and the link
+/// itself is kept.
+pub fn write(target: &Path, content: &[u8]) -> Result<()> {
+    let target = resolve(target)?;
+    let dir = target
+        .parent()
+        .ok_or_else(|| eyre!("{} has no parent directory", target.to_string_lossy()))?;
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.tardis-{}", name, std::process::id()));
+    let metadata = fs::metadata(&target).ok();
+
+    let result = (|| -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        // Restrict the file before the content goes in, so it is never
+        // readable by more users than the target is.
+        if let Some(metadata) = &metadata {
+            file.set_permissions(metadata.permissions())?;
+            keep_owner(&temp, metadata)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp, &target)?;
+        sync_dir(dir)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Could not write {}", target.to_string_lossy()))
+}
+
+/// Remove `target`, or the file it points to if it is a symlink.
+pub fn remove(target: &Path) -> Result<()> {
+    let target = resolve(target)?;
+    fs::remove_file(&target)
+        .with_context(|| format!("Could not remove {}", target.to_string_lossy()))
+}
+
+/// Follow symlinks until reaching a path that is not one, which need not exist.
+pub fn resolve(path: &Path) -> Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINKS {
+        let Ok(link) = fs::read_link(&path) else {
+            return Ok(path);
+        };
+        path = match path.parent() {
+            Some(parent) => parent.join(link),
+            None => link,
+        };
+    }
+    Err(eyre!(
+        "Too many levels of symbolic links at {}",
+        path.to_string_lossy()
+    ))
+}
+
+/// Give `path` the owner of the file it replaces. Only root can give files
+/// away, so being refused is not an error.
+#[cfg(unix)]
+fn keep_owner(path: &Path, metadata: &fs::Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    match std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid())) {
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_path: &Path, _metadata: &fs::Metadata) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Sync the directory so the rename itself survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn keeps_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("run.sh");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write(&target, b"new").unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+    }
+
+    #[test]
+    fn replaces_symlink_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "old").unwrap();
+        symlink("real.txt", &link).unwrap();
+
+        write(&link, b"new").unwrap();
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("real.txt"));
+        assert_eq!(fs::read(&real).unwrap(), b"new");
+    }
+
+    #[test]
+    fn failed_write_leaves_no_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // Renaming a file over a directory fails after the content is written.
+        let target = dir.path().join("taken");
+        fs::create_dir(&target).unwrap();
+
+        assert!(write(&target, b"new").is_err());
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["taken"]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Collapsing runs of byte-identical backups, such as the ones left by
+//! format-on-save without an effective change.
+
+use eyre::{Context, Result};
+use std::collections::BTreeSet;
+
+use crate::hash;
+use crate::history::CodeHistoryFile;
+
+/// The redundant entries of one history folder.
+#[derive(Debug)]
+pub struct Dedup<'a> {
+    pub file: &'a CodeHistoryFile,
+    /// Indices of entries identical to the entry before them.
+    pub drop: BTreeSet<usize>,
+    /// Total size of the dropped entries' backups in bytes.
+    pub bytes: u64,
+}
+
+/// Find entries whose backup is identical to the previous entry's, in
+/// timestamp order, keeping the earliest of each run.
+pub fn plan(files: &[CodeHistoryFile]) -> Result<Vec<Dedup<'_>>> {
+    let mut dedups = Vec::new();
+    for file in files {
+        let entries = &file.info.entries;
+        let mut order: Vec<_> = (0..entries.len()).collect();
+        order.sort_by_key(|&i| entries[i].timestamp);
+
+        let mut drop = BTreeSet::new();
+        let mut bytes = 0;
+        let mut previous = None;
+        for i in order {
+            // A missing backup ends the run.
+            let Ok(content) = std::fs::read(file.dir.join(&entries[i].id)) else {
+                previous = None;
+                continue;
+            };
+            let sha256 = hash::sha256(&content);
+            if previous.as_ref() == Some(&sha256) {
+                drop.insert(i);
+                bytes += content.len() as u64;
+            } else {
+                previous = Some(sha256);
+            }
+        }
+        if !drop.is_empty() {
+            dedups.push(Dedup { file, drop, bytes });
+        }
+    }
+    Ok(dedups)
+}
+
+impl Dedup<'_> {
+    /// Drop the redundant entries from `entries.json`, and with
+    /// `delete_files` their backups as well. Otherwise the backups stay
+    /// behind unreferenced, for `prune` to delete later.
+    pub fn apply(&self, delete_files: bool) -> Result<()> {
+        let entries = &self.file.info.entries;
+        let keep: BTreeSet<_> = (0..entries.len())
+            .filter(|i| !self.drop.contains(i))
+            .collect();
+        self.file.write_entries(&keep)?;
+        if !delete_files {
+            return Ok(());
+        }
+        let kept: BTreeSet<_> = keep.iter().map(|&i| &entries[i].id).collect();
+        for &i in &self.drop {
+            if kept.contains(&entries[i].id) {
+                continue;
+            }
+            let path = self.file.dir.join(&entries[i].id);
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Could not remove {}", path.to_string_lossy()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::folder;
+
+    const RESOURCE: &str = "file:///project/main.rs";
+
+    fn ids(file: &CodeHistoryFile, indices: &BTreeSet<usize>) -> Vec<String> {
+        indices
+            .iter()
+            .map(|&i| file.info.entries[i].id.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_earliest_of_run() {
+        let dir = tempfile::tempdir().unwrap();
+        // Listed out of order, runs go by timestamp.
+        let file = folder(
+            dir.path(),
+            RESOURCE,
+            &[
+                ("C.rs", 3000, Some("y")),
+                ("B.rs", 2000, Some("x")),
+                ("A.rs", 1000, Some("x")),
+                ("D.rs", 4000, Some("x")),
+            ],
+        );
+        let files = [file];
+        let dedups = plan(&files).unwrap();
+        assert_eq!(dedups.len(), 1);
+        assert_eq!(ids(&files[0], &dedups[0].drop), ["B.rs"]);
+        assert_eq!(dedups[0].bytes, 1);
+    }
+
+    #[test]
+    fn missing_backup_ends_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = folder(
+            dir.path(),
+            RESOURCE,
+            &[
+                ("A.rs", 1000, Some("x")),
+                ("B.rs", 2000, None),
+                ("C.rs", 3000, Some("x")),
+            ],
+        );
+        assert!(plan(&[file]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delete_files_spares_referenced_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = folder(
+            dir.path(),
+            RESOURCE,
+            &[
+                ("A.rs", 1000, Some("x")),
+                ("A.rs", 2000, Some("x")),
+                ("B.rs", 3000, Some("y")),
+                ("C.rs", 4000, Some("y")),
+            ],
+        );
+        let files = [file];
+        let dedups = plan(&files).unwrap();
+        assert_eq!(ids(&files[0], &dedups[0].drop), ["A.rs", "C.rs"]);
+        dedups[0].apply(true).unwrap();
+
+        assert!(dir.path().join("A.rs").is_file());
+        assert!(dir.path().join("B.rs").is_file());
+        assert!(!dir.path().join("C.rs").exists());
+        let info: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("entries.json")).unwrap())
+                .unwrap();
+        let kept: Vec<_> = info["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| (e["id"].as_str().unwrap(), e["timestamp"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(kept, [("A.rs", 1000), ("B.rs", 3000)]);
+    }
+}
@@ -0,0 +1,338 @@
+//! Line based diffs between backups and working files.
+
+use std::fmt::Write;
+
+/// Above this many differing lines the diff stops looking for the shortest
+/// edit script and reports the rest as replaced, to bound time and memory.
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Lines added and removed between two texts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl Stat {
+    pub fn changed(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+/// ANSI colors used when coloring is enabled.
+struct Palette {
+    header: &'static str,
+    hunk: &'static str,
+    delete: &'static str,
+    insert: &'static str,
+    reset: &'static str,
+}
+
+impl Palette {
+    fn new(color: bool) -> Self {
+        if color {
+            Palette {
+                header: "\x1b[1m",
+                hunk: "\x1b[36m",
+                delete: "\x1b[31m",
+                insert: "\x1b[32m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Palette {
+                header: "",
+                hunk: "",
+                delete: "",
+                insert: "",
+                reset: "",
+            }
+        }
+    }
+}
+
+/// Whether the content looks binary, in which case it is not diffed by line.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.contains(&0)
+}
+
+/// Count lines added and removed going from `old` to `new`.
+pub fn stat(old: &str, new: &str) -> Stat {
+    let old: Vec<_> = old.split_inclusive('\n').collect();
+    let new: Vec<_> = new.split_inclusive('\n').collect();
+    let mut stat = Stat::default();
+    for op in edit_script(&old, &new) {
+        match op {
+            Op::Equal => {}
+            Op::Delete => stat.deletions += 1,
+            Op::Insert => stat.insertions += 1,
+        }
+    }
+    stat
+}
+
+/// Render a unified diff from `old` to `new`, or an empty string if they are
+/// the same.
+pub fn unified(
+    old_label: &str,
+    new_label: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+    color: bool,
+) -> String {
+    let old: Vec<_> = old.split_inclusive('\n').collect();
+    let new: Vec<_> = new.split_inclusive('\n').collect();
+    let ops = edit_script(&old, &new);
+    let mut out = String::new();
+    if ops.iter().all(|op| *op == Op::Equal) {
+        return out;
+    }
+    let p = Palette::new(color);
+    let _ = writeln!(out, "{}--- {}{}", p.header, old_label, p.reset);
+    let _ = writeln!(out, "{}+++ {}{}", p.header, new_label, p.reset);
+
+    for (start, end) in hunks(&ops, context) {
+        // Line numbers at the start of the hunk.
+        let (mut i, mut j) = (0, 0);
+        for op in &ops[..start] {
+            match op {
+                Op::Equal => (i, j) = (i + 1, j + 1),
+                Op::Delete => i += 1,
+                Op::Insert => j += 1,
+            }
+        }
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|op| **op != Op::Insert)
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|op| **op != Op::Delete)
+            .count();
+        let _ = writeln!(
+            out,
+            "{}@@ -{} +{} @@{}",
+            p.hunk,
+            range(i, old_len),
+            range(j, new_len),
+            p.reset
+        );
+        for op in &ops[start..end] {
+            let (prefix, line, color) = match op {
+                Op::Equal => {
+                    (i, j) = (i + 1, j + 1);
+                    (' ', old[i - 1], "")
+                }
+                Op::Delete => {
+                    i += 1;
+                    ('-', old[i - 1], p.delete)
+                }
+                Op::Insert => {
+                    j += 1;
+                    ('+', new[j - 1], p.insert)
+                }
+            };
+            let reset = if color.is_empty() { "" } else { p.reset };
+            let _ = write!(out, "{}{}{}", color, prefix, line.trim_end_matches('\n'));
+            let _ = writeln!(out, "{}", reset);
+            if !line.ends_with('\n') {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// Format a hunk range the way `diff -u` does.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Group changes into `[start, end)` ranges of `ops`, each padded with up to
+/// `context` unchanged lines. Changes closer than twice the context share a
+/// hunk.
+fn hunks(ops: &[Op], context: usize) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        if *op == Op::Equal {
+            continue;
+        }
+        let start = index.saturating_sub(context);
+        let end = (index + 1 + context).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+/// The shortest edit script from `old` to `new` (Myers' algorithm), after
+/// stripping the common prefix and suffix.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+    ops.extend(myers(a, b));
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+    ops
+}
+
+fn myers(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+
+    for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                return backtrack(&trace, offset, n, m);
+            }
+        }
+    }
+
+    // Too many differences: report everything as replaced.
+    let mut ops = vec![Op::Delete; a.len()];
+    ops.extend(std::iter::repeat_n(Op::Insert, b.len()));
+    ops
+}
+
+fn backtrack(trace: &[Vec<isize>], offset: isize, n: isize, m: isize) -> Vec<Op> {
+    let (mut x, mut y) = (n, m);
+    let mut ops = Vec::new();
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+            (x, y) = (prev_x, prev_y);
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replay `ops` on `old`, which must give `new`.
+    fn replay<'a>(ops: &[Op], old: &[&'a str], new: &[&'a str]) -> Vec<&'a str> {
+        let (mut i, mut j) = (0, 0);
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                Op::Equal => {
+                    out.push(old[i]);
+                    (i, j) = (i + 1, j + 1);
+                }
+                Op::Delete => i += 1,
+                Op::Insert => {
+                    out.push(new[j]);
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (old.len(), new.len()));
+        out
+    }
+
+    #[test]
+    fn edit_script_is_shortest() {
+        // The example from Myers' paper, with an edit distance of 5.
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let ops = edit_script(&old, &new);
+        assert_eq!(replay(&ops, &old, &new), new);
+        assert_eq!(ops.iter().filter(|op| **op != Op::Equal).count(), 5);
+    }
+
+    #[test]
+    fn edit_script_edges() {
+        assert_eq!(edit_script(&[], &[]), []);
+        assert_eq!(edit_script(&["a"], &["a"]), [Op::Equal]);
+        assert_eq!(edit_script(&[], &["a", "b"]), [Op::Insert, Op::Insert]);
+        assert_eq!(edit_script(&["a", "b"], &[]), [Op::Delete, Op::Delete]);
+        assert_eq!(
+            edit_script(&["x", "a", "y"], &["x", "b", "y"]),
+            [Op::Equal, Op::Delete, Op::Insert, Op::Equal]
+        );
+    }
+
+    #[test]
+    fn unified_matches_diff_u() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\nextra";
+        assert_eq!(
+            unified("a/f", "b/f", old, new, 1, false),
+            "--- a/f\n+++ b/f\n\
+             @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+             @@ -10 +10,2 @@\n 10\n+extra\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn unified_merges_close_hunks() {
+        let old = "1\n2\n3\n4\n5\n";
+        let new = "one\n2\n3\n4\nfive\n";
+        let diff = unified("a/f", "b/f", old, new, 2, false);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("@@ -1,5 +1,5 @@\n"));
+    }
+
+    #[test]
+    fn unified_from_empty() {
+        assert_eq!(
+            unified("a/f", "b/f", "", "a\n", 3, false),
+            "--- a/f\n+++ b/f\n@@ -0,0 +1 @@\n+a\n"
+        );
+        assert_eq!(unified("a/f", "b/f", "same\n", "same\n", 3, false), "");
+    }
+}
@@ -0,0 +1,112 @@
+//! Disk usage of the history store, grouped by workspace, directory or file.
+
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::history::CodeHistoryFile;
+use crate::output::DuRecord;
+use crate::workspace;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The project of each file, see `list --global`
+    Workspace,
+    /// The directory containing each file
+    Dir,
+    /// Each file on its own
+    File,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// Largest first
+    Size,
+    /// Most entries first
+    Entries,
+    /// Oldest entry first
+    Oldest,
+    /// Most recent entry first
+    Newest,
+    /// By path
+    Name,
+}
+
+/// Add up the history folders of `files` by group. Paths below `base` are
+/// shown relative to it; resources that are not files are grouped by their
+/// URI, or by scheme unless grouping by file.
+pub fn usage(files: &[CodeHistoryFile], base: Option<&Path>, by: GroupBy) -> Result<Vec<DuRecord>> {
+    let mut groups: BTreeMap<String, DuRecord> = BTreeMap::new();
+    for file in files {
+        let name = if file.is_file() {
+            let path = file.current_file();
+            let group = match by {
+                GroupBy::Workspace => workspace::root(&path),
+                GroupBy::Dir => path.parent().unwrap_or(&path).to_path_buf(),
+                GroupBy::File => path,
+            };
+            match base.and_then(|base| group.strip_prefix(base).ok()) {
+                Some(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+                Some(relative) => relative.to_string_lossy().into_owned(),
+                None => group.to_string_lossy().into_owned(),
+            }
+        } else if by == GroupBy::File {
+            file.info.resource.to_string()
+        } else {
+            format!("{}:", file.info.resource.scheme())
+        };
+
+        let record = groups.entry(name.clone()).or_insert_with(|| DuRecord {
+            name,
+            size: 0,
+            folders: 0,
+            entries: 0,
+            oldest: None,
+            newest: None,
+        });
+        record.folders += 1;
+        record.entries += file.info.entries.len();
+        record.size += folder_size(&file.dir)?;
+        for entry in &file.info.entries {
+            record.oldest = Some(
+                record
+                    .oldest
+                    .map_or(entry.timestamp, |t| t.min(entry.timestamp)),
+            );
+            record.newest = Some(
+                record
+                    .newest
+                    .map_or(entry.timestamp, |t| t.max(entry.timestamp)),
+            );
+        }
+    }
+    Ok(groups.into_values().collect())
+}
+
+/// Sort records in place, see [`SortBy`].
+pub fn sort(records: &mut [DuRecord], by: SortBy) {
+    use std::cmp::Reverse;
+    match by {
+        SortBy::Size => records.sort_by_key(|r| Reverse(r.size)),
+        SortBy::Entries => records.sort_by_key(|r| Reverse(r.entries)),
+        SortBy::Oldest => records.sort_by_key(|r| r.oldest),
+        SortBy::Newest => records.sort_by_key(|r| Reverse(r.newest)),
+        SortBy::Name => records.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+}
+
+/// Size of the files directly inside `dir`, which is how history folders are
+/// laid out.
+fn folder_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for e in std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read {}", dir.to_string_lossy()))?
+    {
+        let metadata = e?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
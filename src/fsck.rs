@@ -0,0 +1,255 @@
+//! Checks of the history store itself, regardless of --dir.
+
+use chrono::{DateTime, Local, Utc};
+use eyre::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::history::{CodeHistoryEntry, CodeHistoryFile, Problem, Scan};
+use crate::output::{Check, FsckRecord};
+
+/// Check every history folder found by the scan, including the ones that
+/// could not be read.
+pub fn check(scan: &Scan, now: DateTime<Utc>) -> Result<Vec<FsckRecord>> {
+    let mut records: Vec<_> = scan
+        .diagnostics
+        .iter()
+        .map(|diagnostic| FsckRecord {
+            check: match diagnostic.problem {
+                Problem::UnknownVersion(_) => Check::UnknownVersion,
+                Problem::MissingBackup(_) => Check::MissingBackup,
+                Problem::Io(_) | Problem::Parse(_) => Check::Unreadable,
+            },
+            path: diagnostic.path.clone(),
+            detail: diagnostic.problem.to_string(),
+            repaired: false,
+        })
+        .collect();
+
+    let mut by_resource: BTreeMap<(&Path, &str), Vec<&Path>> = BTreeMap::new();
+    for file in &scan.files {
+        let root = file.dir.parent().unwrap_or(&file.dir);
+        by_resource
+            .entry((root, file.info.resource.as_str()))
+            .or_default()
+            .push(&file.dir);
+
+        let mut referenced = BTreeSet::new();
+        for entry in &file.info.entries {
+            let backup = file.dir.join(&entry.id);
+            referenced.insert(backup.clone());
+            if !backup.is_file() {
+                records.push(FsckRecord {
+                    check: Check::MissingBackup,
+                    detail: format!("entry {} has no backup file", entry.id.to_string_lossy()),
+                    path: backup,
+                    repaired: false,
+                });
+            }
+            if entry.timestamp > now {
+                records.push(FsckRecord {
+                    check: Check::FutureTimestamp,
+                    path: file.dir.join(&entry.id),
+                    detail: format!(
+                        "entry {} is dated {}",
+                        entry.id.to_string_lossy(),
+                        entry
+                            .timestamp
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                    ),
+                    repaired: false,
+                });
+            }
+        }
+
+        // A folder that cannot be listed is reported like the unreadable
+        // ones found by the scan, and the audit goes on.
+        let listing = std::fs::read_dir(&file.dir).and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let paths = match listing {
+            Ok(paths) => paths,
+            Err(err) => {
+                records.push(FsckRecord {
+                    check: Check::Unreadable,
+                    path: file.dir.clone(),
+                    detail: Problem::Io(err.to_string()).to_string(),
+                    repaired: false,
+                });
+                continue;
+            }
+        };
+        for path in paths {
+            if path.is_file() && !path.ends_with("entries.json") && !referenced.contains(&path) {
+                records.push(FsckRecord {
+                    check: Check::Orphan,
+                    path,
+                    detail: "not referenced by any entry".to_string(),
+                    repaired: false,
+                });
+            }
+        }
+    }
+
+    for ((_, resource), dirs) in by_resource {
+        if dirs.len() < 2 {
+            continue;
+        }
+        for dir in &dirs {
+            records.push(FsckRecord {
+                check: Check::Duplicate,
+                path: dir.to_path_buf(),
+                detail: format!("{} history folders record {}", dirs.len(), resource),
+                repaired: false,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// A fix that loses nothing: dropping entries whose backup is missing from
+/// `entries.json`, keeping every other field as it was.
+#[derive(Debug)]
+pub struct Repair<'a> {
+    pub file: &'a CodeHistoryFile,
+    /// Indices of the entries kept.
+    pub keep: BTreeSet<usize>,
+}
+
+/// Find the history folders that `--repair` would rewrite. Folders that are
+/// gone are left alone, `check` reports them as unreadable.
+pub fn repairs(scan: &Scan) -> Vec<Repair<'_>> {
+    let mut repairs = Vec::new();
+    for file in &scan.files {
+        if !file.dir.is_dir() {
+            continue;
+        }
+        let keep: BTreeSet<_> = (0..file.info.entries.len())
+            .filter(|&i| file.dir.join(&file.info.entries[i].id).is_file())
+            .collect();
+        if keep.len() < file.info.entries.len() {
+            repairs.push(Repair { file, keep });
+        }
+    }
+    repairs
+}
+
+impl Repair<'_> {
+    /// The entries that are dropped.
+    pub fn dropped(&self) -> impl Iterator<Item = &CodeHistoryEntry> {
+        let entries = &self.file.info.entries;
+        (0..entries.len())
+            .filter(|i| !self.keep.contains(i))
+            .map(move |i| &entries[i])
+    }
+
+    /// Rewrite `entries.json` and mark the problems it fixes as repaired.
+    pub fn apply(&self, records: &mut [FsckRecord]) -> Result<()> {
+        self.file.write_entries(&self.keep)?;
+        for record in records.iter_mut() {
+            if record.check == Check::MissingBackup && record.path.parent() == Some(&self.file.dir)
+            {
+                record.repaired = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::folder;
+
+    #[test]
+    fn repair_drops_only_missing_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = folder(
+            dir.path(),
+            "file:///project/main.rs",
+            &[
+                ("A.rs", 1000, Some("a")),
+                ("B.rs", 2000, None),
+                ("C.rs", 3000, Some("c")),
+            ],
+        );
+        std::fs::write(dir.path().join("stray.rs"), "stray").unwrap();
+        let scan = Scan {
+            files: vec![file],
+            diagnostics: Vec::new(),
+        };
+        let mut records = check(&scan, Utc::now()).unwrap();
+        let checks: Vec<_> = records.iter().map(|r| r.check).collect();
+        assert_eq!(checks, [Check::MissingBackup, Check::Orphan]);
+
+        let repairs = repairs(&scan);
+        assert_eq!(repairs.len(), 1);
+        let dropped: Vec<_> = repairs[0].dropped().map(|e| e.id.clone()).collect();
+        assert_eq!(dropped, [Path::new("B.rs")]);
+        repairs[0].apply(&mut records).unwrap();
+        assert!(records[0].repaired);
+        assert!(!records[1].repaired);
+
+        let info: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("entries.json")).unwrap())
+                .unwrap();
+        let ids: Vec<_> = info["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["A.rs", "C.rs"]);
+        assert_eq!(info["resource"], "file:///project/main.rs");
+        assert!(dir.path().join("stray.rs").is_file());
+    }
+
+    #[test]
+    fn unreadable_folder_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let gone = folder(
+            &dir.path().join("gone"),
+            "file:///project/gone.rs",
+            &[("A.rs", 1000, Some("a"))],
+        );
+        let kept = folder(
+            &dir.path().join("kept"),
+            "file:///project/main.rs",
+            &[("A.rs", 1000, Some("a"))],
+        );
+        std::fs::write(dir.path().join("kept/stray.rs"), "stray").unwrap();
+        // Removed after the scan, so listing it fails.
+        std::fs::remove_dir_all(&gone.dir).unwrap();
+        let scan = Scan {
+            files: vec![gone, kept],
+            diagnostics: Vec::new(),
+        };
+        let checks: Vec<_> = check(&scan, Utc::now())
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.check, r.path))
+            .collect();
+        assert!(checks.contains(&(Check::Unreadable, dir.path().join("gone"))));
+        assert!(checks.contains(&(Check::Orphan, dir.path().join("kept/stray.rs"))));
+        assert!(repairs(&scan).is_empty());
+    }
+
+    #[test]
+    fn nothing_to_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = folder(
+            dir.path(),
+            "file:///project/main.rs",
+            &[("A.rs", 1000, Some("a"))],
+        );
+        let scan = Scan {
+            files: vec![file],
+            diagnostics: Vec::new(),
+        };
+        assert!(check(&scan, Utc::now()).unwrap().is_empty());
+        assert!(repairs(&scan).is_empty());
+    }
+}
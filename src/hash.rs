@@ -0,0 +1,49 @@
+//! SHA-256 of file contents, used to compare working files with backups.
+
+use eyre::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::Path;
+
+/// The SHA-256 of `data` as lowercase hex, as printed by `sha256sum`.
+pub fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// The SHA-256 of a file's content, read in chunks.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    File::open(path)
+        .and_then(|mut file| std::io::copy(&mut file, &mut hasher))
+        .with_context(|| format!("Could not read {}", path.to_string_lossy()))?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercase_hex() {
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn file_matches_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, vec![b'a'; 100_000]).unwrap();
+        assert_eq!(sha256_file(&path).unwrap(), sha256(&vec![b'a'; 100_000]));
+    }
+}
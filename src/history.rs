@@ -0,0 +1,427 @@
+use chrono::serde::*;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Context, Result};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use crate::atomic;
+use crate::hash;
+use crate::roots::{Flavor, HistoryRoot};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CodeHistoryFile {
+    pub dir: PathBuf,
+    pub flavor: Option<Flavor>,
+    pub info: CodeHistoryInfo,
+}
+
+impl CodeHistoryFile {
+    /// Path of the file this history belongs to.
+    ///
+    /// For `vscode-remote` resources this is the path on the remote host.
+    pub fn current_file(&self) -> PathBuf {
+        let resource = &self.info.resource;
+        if let Ok(path) = resource.to_file_path() {
+            return path;
+        }
+        let path = percent_decode_str(resource.path()).decode_utf8_lossy();
+        PathBuf::from(path.as_ref())
+    }
+
+    /// The remote the file was edited on, e.g. `ssh-remote+devbox` or
+    /// `wsl+Ubuntu`, if it was not a local file.
+    pub fn remote_authority(&self) -> Option<String> {
+        if !self.is_scheme("vscode-remote") {
+            return None;
+        }
+        let host = self.info.resource.host_str()?;
+        Some(percent_decode_str(host).decode_utf8_lossy().into_owned())
+    }
+
+    /// Human readable label for where this history came from, e.g.
+    /// `Cursor (ssh-remote+devbox)`.
+    pub fn origin(&self) -> String {
+        let flavor = self
+            .flavor
+            .map_or_else(|| "unknown".to_string(), |f| f.to_string());
+        match self.remote_authority() {
+            Some(remote) => format!("{} ({})", flavor, remote),
+            None => flavor,
+        }
+    }
+
+    pub fn backup_files(&self) -> Vec<(DateTime<Utc>, PathBuf)> {
+        self.info
+            .entries
+            .iter()
+            .map(|e| (e.timestamp, self.dir.join(&e.id)))
+            .collect()
+    }
+
+    /// Whether the file no longer exists on disk.
+    pub fn is_deleted(&self) -> bool {
+        self.current_file().symlink_metadata().is_err()
+    }
+
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.info.resource.scheme() == scheme
+    }
+
+    /// Whether the resource is a file on disk, either local or on a remote
+    /// host, as opposed to e.g. an untitled buffer or a git virtual document.
+    pub fn is_file(&self) -> bool {
+        self.is_scheme("file") || self.is_scheme("vscode-remote")
+    }
+
+    /// Rewrite `entries.json` with only the entries at the given indices,
+    /// keeping every other field as it was.
+    pub fn write_entries(&self, keep: &BTreeSet<usize>) -> Result<()> {
+        let entries: Vec<_> = keep.iter().map(|&i| &self.info.entries[i]).collect();
+        let mut info = serde_json::to_value(&self.info)?;
+        info["entries"] = serde_json::to_value(entries)?;
+        atomic::write(&self.dir.join("entries.json"), &serde_json::to_vec(&info)?)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CodeHistoryInfo {
+    pub version: u32,
+    pub resource: url::Url,
+    pub entries: Vec<CodeHistoryEntry>,
+    /// Fields not used here, kept so that rewriting the file preserves them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CodeHistoryEntry {
+    pub id: PathBuf,
+    #[serde(with = "ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+    /// Fields not used here, such as `source`, kept so that rewriting the
+    /// file preserves them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// All history recorded for one file. A file edited with several flavors has
+/// a history folder for each of them.
+#[derive(Debug)]
+pub struct TrackedFile<'a> {
+    pub path: PathBuf,
+    pub history: Vec<&'a CodeHistoryFile>,
+}
+
+impl TrackedFile<'_> {
+    /// Backups from every history folder of the file, oldest first. Entries
+    /// whose backup is missing are left out; the scan reports them.
+    pub fn backup_files(&self) -> Vec<(DateTime<Utc>, PathBuf)> {
+        let mut backups: Vec<_> = self
+            .history
+            .iter()
+            .flat_map(|h| h.backup_files())
+            .filter(|(_, path)| path.is_file())
+            .collect();
+        backups.sort_by_key(|(ts, _)| *ts);
+        backups
+    }
+
+    /// Whether the file no longer exists on disk.
+    pub fn is_deleted(&self) -> bool {
+        self.path.symlink_metadata().is_err()
+    }
+
+    /// Whether the working file holds changes that are in none of its
+    /// backups, e.g. edits made by another editor, which restoring would
+    /// throw away. Content matching `written`, the hash of what tardis last
+    /// wrote to the file, is not counted. The modification time rules out
+    /// most files before any content is compared.
+    pub fn has_unrecorded_changes(&self, written: Option<&str>) -> Result<bool> {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return Ok(false);
+        };
+        let backups = self.backup_files();
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+        if backups.last().is_some_and(|(ts, _)| modified <= *ts) {
+            return Ok(false);
+        }
+        let current = std::fs::read(&self.path)
+            .with_context(|| format!("Could not read {}", self.path.to_string_lossy()))?;
+        if written == Some(hash::sha256(&current).as_str()) {
+            return Ok(false);
+        }
+        for (_, backup) in backups.iter().rev() {
+            let same_size = std::fs::metadata(backup).is_ok_and(|m| m.len() == metadata.len());
+            if same_size && std::fs::read(backup).is_ok_and(|content| content == current) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Group history folders by the file they belong to, sorted by path.
+pub fn tracked_files(files: &[CodeHistoryFile]) -> Vec<TrackedFile<'_>> {
+    let mut by_path: BTreeMap<PathBuf, Vec<&CodeHistoryFile>> = BTreeMap::new();
+    for file in files {
+        by_path.entry(file.current_file()).or_default().push(file);
+    }
+    by_path
+        .into_iter()
+        .map(|(path, history)| TrackedFile { path, history })
+        .collect()
+}
+
+/// The `entries.json` format version this was written against.
+const KNOWN_VERSION: u32 = 1;
+/// Diagnostics shown in the warning before the rest is left out.
+const MAX_SHOWN: usize = 5;
+
+/// A problem found while scanning the history. Scanning goes on with
+/// everything else.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+    /// A file or directory could not be read, so it was skipped.
+    Io(String),
+    /// An `entries.json` could not be parsed, so it was skipped.
+    Parse(String),
+    /// An `entries.json` has a version other than [`KNOWN_VERSION`]. It is
+    /// read anyway.
+    UnknownVersion(u32),
+    /// An entry's backup file does not exist.
+    MissingBackup(String),
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.to_string_lossy(), self.problem)
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Io(err) => write!(f, "could not read: {}", err),
+            Problem::Parse(err) => write!(f, "could not parse: {}", err),
+            Problem::UnknownVersion(version) => write!(
+                f,
+                "unknown version {}, read as version {}",
+                version, KNOWN_VERSION
+            ),
+            Problem::MissingBackup(id) => write!(f, "backup {} is missing", id),
+        }
+    }
+}
+
+/// The history folders found by [`scan`], and the problems found on the way.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub files: Vec<CodeHistoryFile>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Scan {
+    /// Report entries of the remaining files whose backup is missing. Kept
+    /// separate from [`scan`] so that only the files a command works on are
+    /// checked.
+    pub fn check_backups(&mut self) {
+        for file in &self.files {
+            for entry in &file.info.entries {
+                let backup = file.dir.join(&entry.id);
+                if !backup.is_file() {
+                    self.diagnostics.push(Diagnostic {
+                        path: backup,
+                        problem: Problem::MissingBackup(entry.id.to_string_lossy().into_owned()),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Warn about the diagnostics on stderr, or fail if `strict` is set.
+    pub fn report(&self, strict: bool) -> Result<()> {
+        if self.diagnostics.is_empty() {
+            return Ok(());
+        }
+        let count = self.diagnostics.len();
+        let summary = format!(
+            "{} problem{} in the history",
+            count,
+            if count == 1 { "" } else { "s" }
+        );
+        if strict {
+            let lines: Vec<_> = self
+                .diagnostics
+                .iter()
+                .map(|d| format!("  {}", d))
+                .collect();
+            return Err(eyre!("{}:\n{}", summary, lines.join("\n")));
+        }
+        eprintln!("warning: {}:", summary);
+        for diagnostic in self.diagnostics.iter().take(MAX_SHOWN) {
+            eprintln!("  {}", diagnostic);
+        }
+        if count > MAX_SHOWN {
+            eprintln!("  ... and {} more", count - MAX_SHOWN);
+        }
+        eprintln!("Pass --strict to fail on them instead");
+        Ok(())
+    }
+}
+
+/// Read every `entries.json` below the given roots. Files that cannot be
+/// read or parsed are skipped and reported in [`Scan::diagnostics`].
+pub fn scan(roots: &[HistoryRoot]) -> Scan {
+    let mut scan = Scan::default();
+    for root in roots {
+        for e in walkdir::WalkDir::new(&root.path).max_depth(3) {
+            let e = match e {
+                Ok(e) => e,
+                Err(err) => {
+                    scan.diagnostics.push(Diagnostic {
+                        path: err.path().unwrap_or(&root.path).to_path_buf(),
+                        problem: Problem::Io(err.to_string()),
+                    });
+                    continue;
+                }
+            };
+            if !e.file_type().is_file() || !e.path().ends_with("entries.json") {
+                continue;
+            }
+            let diagnostic = |problem| Diagnostic {
+                path: e.path().to_path_buf(),
+                problem,
+            };
+            let info = match read_to_string(e.path()) {
+                Ok(info) => info,
+                Err(err) => {
+                    scan.diagnostics
+                        .push(diagnostic(Problem::Io(err.to_string())));
+                    continue;
+                }
+            };
+            let info: CodeHistoryInfo = match serde_json::from_str(&info) {
+                Ok(info) => info,
+                Err(err) => {
+                    scan.diagnostics
+                        .push(diagnostic(Problem::Parse(err.to_string())));
+                    continue;
+                }
+            };
+            if info.version != KNOWN_VERSION {
+                scan.diagnostics
+                    .push(diagnostic(Problem::UnknownVersion(info.version)));
+            }
+            let Some(dir) = e.path().parent() else {
+                continue;
+            };
+            scan.files.push(CodeHistoryFile {
+                dir: dir.to_path_buf(),
+                flavor: root.flavor,
+                info,
+            });
+        }
+    }
+    scan
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Write a history folder for `resource` into `dir` and read it back.
+    /// Each backup is an id, a timestamp in milliseconds and its content,
+    /// `None` leaving the backup file missing.
+    pub(crate) fn folder(
+        dir: &Path,
+        resource: &str,
+        backups: &[(&str, i64, Option<&str>)],
+    ) -> CodeHistoryFile {
+        std::fs::create_dir_all(dir).unwrap();
+        let mut entries = Vec::new();
+        for (id, timestamp, content) in backups {
+            if let Some(content) = content {
+                std::fs::write(dir.join(id), content).unwrap();
+            }
+            entries.push(serde_json::json!({"id": id, "timestamp": timestamp}));
+        }
+        let info = serde_json::json!({"version": 1, "resource": resource, "entries": entries});
+        std::fs::write(dir.join("entries.json"), info.to_string()).unwrap();
+        CodeHistoryFile {
+            dir: dir.to_path_buf(),
+            flavor: None,
+            info: serde_json::from_value(info).unwrap(),
+        }
+    }
+
+    /// 2020-09-13, long before any working file in the tests was written.
+    const PAST: i64 = 1_600_000_000_000;
+
+    /// Check a working file holding `current` against backups of `old` and
+    /// `newer`, both older than the working file.
+    fn unrecorded(current: &str, written: Option<&str>) -> bool {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        let resource = url::Url::from_file_path(&path).unwrap();
+        let history = folder(
+            &dir.path().join("history"),
+            resource.as_str(),
+            &[
+                ("A.rs", PAST, Some("old")),
+                ("B.rs", PAST + 1000, Some("newer")),
+            ],
+        );
+        std::fs::write(&path, current).unwrap();
+        let file = TrackedFile {
+            path,
+            history: vec![&history],
+        };
+        file.has_unrecorded_changes(written).unwrap()
+    }
+
+    #[test]
+    fn older_backup_is_recorded() {
+        assert!(!unrecorded("old", None));
+        assert!(!unrecorded("newer", None));
+    }
+
+    #[test]
+    fn content_in_no_backup_is_unrecorded() {
+        assert!(unrecorded("edited", None));
+        assert!(unrecorded("edited", Some(&hash::sha256(b"other"))));
+    }
+
+    #[test]
+    fn last_write_is_recorded() {
+        assert!(!unrecorded("edited", Some(&hash::sha256(b"edited"))));
+    }
+
+    #[test]
+    fn newer_backup_rules_out_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "edited").unwrap();
+        let resource = url::Url::from_file_path(&path).unwrap();
+        let future = Utc::now().timestamp_millis() + 60_000;
+        let history = folder(
+            &dir.path().join("history"),
+            resource.as_str(),
+            &[("A.rs", future, Some("old"))],
+        );
+        let file = TrackedFile {
+            path,
+            history: vec![&history],
+        };
+        assert!(!file.has_unrecorded_changes(None).unwrap());
+    }
+}
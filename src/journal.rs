@@ -0,0 +1,327 @@
+//! A record of every operation that wrote files, with copies of the content
+//! it replaced, so that the operation can be undone.
+//!
+//! Each operation gets its own directory below the journal directory, named
+//! by its id, holding an `operation.json` and the saved copies.
+
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::hash;
+use crate::plan::{Action, Plan, Source};
+
+/// Environment variable overriding where the journal is kept.
+static JOURNAL_DIR_ENV: &str = "TARDIS_JOURNAL_DIR";
+static OPERATION_FILE: &str = "operation.json";
+/// Operation name of undos, which are journaled but not undone themselves.
+pub static UNDO: &str = "undo";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: String,
+    pub time: DateTime<Utc>,
+    /// The command that wrote the files, e.g. `restore`.
+    pub operation: String,
+    #[serde(default)]
+    pub undone: bool,
+    pub files: Vec<JournalFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalFile {
+    pub target: PathBuf,
+    /// Name of the copy saved before the target was written, or `None` if
+    /// the target did not exist.
+    pub saved: Option<String>,
+    /// SHA-256 of the content written, or `None` if the target was removed.
+    #[serde(default)]
+    pub written: Option<String>,
+}
+
+pub struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    /// The journal in `$TARDIS_JOURNAL_DIR`, or in the user's data directory.
+    pub fn open() -> Result<Self> {
+        let dir = match std::env::var_os(JOURNAL_DIR_ENV) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::data_dir()
+                .ok_or_else(|| eyre!("Could not find data directory for the journal"))?
+                .join("code-tardis")
+                .join("journal"),
+        };
+        Ok(Journal { dir })
+    }
+
+    /// Start recording a new operation.
+    pub fn start(&self, operation: &str) -> Result<Record> {
+        let time = Utc::now();
+        let base = format!(
+            "{}-{}",
+            time.format("%Y%m%d-%H%M%S-%3f"),
+            std::process::id()
+        );
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Could not create journal {}", self.dir.to_string_lossy()))?;
+        // Operations started within the same millisecond get a counter.
+        let mut id = base.clone();
+        let mut dir = self.dir.join(&id);
+        for n in 1.. {
+            match std::fs::create_dir(&dir) {
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    id = format!("{}-{}", base, n);
+                    dir = self.dir.join(&id);
+                }
+                result => {
+                    result.with_context(|| {
+                        format!("Could not create journal {}", dir.to_string_lossy())
+                    })?;
+                    break;
+                }
+            }
+        }
+        let record = Record {
+            dir,
+            operation: Operation {
+                id,
+                time,
+                operation: operation.to_string(),
+                undone: false,
+                files: Vec::new(),
+            },
+        };
+        record.write()?;
+        Ok(record)
+    }
+
+    /// Every recorded operation, oldest first.
+    pub fn operations(&self) -> Result<Vec<Operation>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut operations = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path().join(OPERATION_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            let json = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read {}", path.to_string_lossy()))?;
+            let operation: Operation = serde_json::from_str(&json)
+                .with_context(|| format!("Could not parse {}", path.to_string_lossy()))?;
+            operations.push(operation);
+        }
+        operations.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(operations)
+    }
+
+    /// The operation with the given id, or the most recent one that can
+    /// still be undone.
+    pub fn undoable(&self, id: Option<&str>) -> Result<Operation> {
+        let operations = self.operations()?;
+        let operation = match id {
+            Some(id) => operations
+                .into_iter()
+                .find(|op| op.id == id)
+                .ok_or_else(|| eyre!("No operation {} in the journal", id))?,
+            None => operations
+                .into_iter()
+                .rev()
+                .find(|op| !op.undone && op.operation != UNDO)
+                .ok_or_else(|| eyre!("Nothing to undo"))?,
+        };
+        if operation.undone {
+            return Err(eyre!("Operation {} was already undone", operation.id));
+        }
+        Ok(operation)
+    }
+
+    /// The hash of what was last written to each file, going by every
+    /// recorded operation.
+    pub fn last_written(&self) -> Result<HashMap<PathBuf, String>> {
+        let mut written = HashMap::new();
+        for operation in self.operations()? {
+            for file in operation.files {
+                match file.written {
+                    Some(hash) => written.insert(file.target, hash),
+                    None => written.remove(&file.target),
+                };
+            }
+        }
+        Ok(written)
+    }
+
+    /// Plan putting every file written by `operation` back as it was.
+    pub fn undo_plan(&self, operation: &Operation) -> Result<Plan> {
+        let dir = self.dir.join(&operation.id);
+        let mut plan = Plan::new(UNDO);
+        for file in &operation.files {
+            // A target written twice goes back to its state before the first write.
+            if plan.actions.iter().any(|a| a.target == file.target) {
+                continue;
+            }
+            let source = match &file.saved {
+                Some(saved) => Source::Journal(dir.join(saved)),
+                None => Source::Remove,
+            };
+            plan.actions.push(Action::new(
+                file.target.to_string_lossy().into_owned(),
+                source,
+                file.target.clone(),
+            )?);
+        }
+        Ok(plan)
+    }
+
+    pub fn mark_undone(&self, mut operation: Operation) -> Result<()> {
+        operation.undone = true;
+        Record {
+            dir: self.dir.join(&operation.id),
+            operation,
+        }
+        .write()
+    }
+}
+
+/// An operation being recorded.
+pub struct Record {
+    dir: PathBuf,
+    operation: Operation,
+}
+
+impl Record {
+    /// Save the current content of `target`, if any, before it is written.
+    pub fn save(&mut self, target: &Path) -> Result<()> {
+        let saved = if target.is_file() {
+            let name = self.operation.files.len().to_string();
+            std::fs::copy(target, self.dir.join(&name)).with_context(|| {
+                format!("Could not save {} to the journal", target.to_string_lossy())
+            })?;
+            Some(name)
+        } else {
+            None
+        };
+        self.operation.files.push(JournalFile {
+            target: target.to_path_buf(),
+            saved,
+            written: None,
+        });
+        self.write()
+    }
+
+    /// Record what was written to the target of the last [`Record::save`].
+    pub fn wrote(&mut self, target: &Path) -> Result<()> {
+        let written = match target.is_file() {
+            true => Some(hash::sha256_file(target)?),
+            false => None,
+        };
+        if let Some(file) = self.operation.files.last_mut() {
+            file.written = written;
+        }
+        self.write()
+    }
+
+    /// Finish recording and return the operation's id. Operations that did
+    /// not write anything are dropped.
+    pub fn finish(self) -> Result<String> {
+        if self.operation.files.is_empty() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(self.operation.id)
+    }
+
+    fn write(&self) -> Result<()> {
+        atomic::write(
+            &self.dir.join(OPERATION_FILE),
+            &serde_json::to_vec_pretty(&self.operation)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply a plan writing each source over `target` in turn, then undo it.
+    fn write_and_undo(dir: &Path, target: &Path, sources: &[&str]) -> Journal {
+        let journal = Journal {
+            dir: dir.join("journal"),
+        };
+        let mut plan = Plan::new("restore");
+        for (i, content) in sources.iter().enumerate() {
+            let source = dir.join(format!("source{}", i));
+            std::fs::write(&source, content).unwrap();
+            plan.actions.push(
+                Action::new("target".to_string(), Source::Current(source), target.into()).unwrap(),
+            );
+        }
+        plan.apply(&journal).unwrap();
+        assert_eq!(
+            journal.last_written().unwrap()[target],
+            hash::sha256(sources.last().unwrap().as_bytes())
+        );
+
+        let operation = journal.undoable(None).unwrap();
+        journal
+            .undo_plan(&operation)
+            .unwrap()
+            .apply(&journal)
+            .unwrap();
+        journal.mark_undone(operation).unwrap();
+        journal
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal {
+            dir: dir.path().to_path_buf(),
+        };
+        let ids: Vec<_> = (0..3)
+            .map(|_| journal.start("restore").unwrap().operation.id)
+            .collect();
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+    }
+
+    #[test]
+    fn undo_restores_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "original").unwrap();
+
+        let journal = write_and_undo(dir.path(), &target, &["restored"]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "original");
+        assert_eq!(
+            journal.last_written().unwrap()[&target],
+            hash::sha256(b"original")
+        );
+        assert!(journal.undoable(None).is_err());
+    }
+
+    #[test]
+    fn undo_removes_recreated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+
+        let journal = write_and_undo(dir.path(), &target, &["recreated"]);
+        assert!(!target.exists());
+        assert!(!journal.last_written().unwrap().contains_key(&target));
+    }
+
+    #[test]
+    fn undo_of_double_write_restores_first_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "original").unwrap();
+
+        write_and_undo(dir.path(), &target, &["first", "second"]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "original");
+    }
+}
@@ -1,19 +1,84 @@
-use chrono::serde::*;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, TimeDelta, Utc};
 use eyre::{eyre, Context, Result};
-use serde::{Deserialize, Serialize};
-use std::fs::read_to_string;
 
-use std::path::{PathBuf, Path};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
 
-static CODE_HISTORY_DIR: &str = "Library/Application Support/Code/User/History";
+use history::{CodeHistoryFile, TrackedFile};
+use journal::Journal;
+use output::{Format, ListRecord, LogRecord, State};
+use plan::{Action, Plan, Source};
+use roots::HistoryRoot;
+use select::Selector;
+
+mod atomic;
+mod dedup;
+mod diff;
+mod du;
+mod fsck;
+mod hash;
+mod history;
+mod journal;
+mod output;
+mod plan;
+mod prompt;
+mod prune;
+mod rollback;
+mod roots;
+mod select;
+mod snapshot;
+mod status;
+mod timeexpr;
+mod workspace;
+
+/// Exit code of `status` and `fsck` when they find something to report.
+const EXIT_DIRTY: i32 = 3;
+
+static FSCK_HELP: &str = "\
+Exit status:
+  0  no problems, or all of them were repaired
+  3  problems remain
+  1  an error occurred";
+
+static STATUS_HELP: &str = "\
+Exit status:
+  0  every file matches its newest backup
+  3  some file is modified, deleted or never recorded
+  1  an error occurred";
+
+/// Environment variable with extra history directories, separated like `$PATH`.
+static HISTORY_DIR_ENV: &str = "TARDIS_HISTORY_DIR";
 
 #[derive(Parser, Debug)]
 struct Tardis {
     #[arg(short = 'C', long, default_value = ".")]
     dir: PathBuf,
 
+    /// Also read history from this `User/History` directory (repeatable).
+    /// Directories listed in $TARDIS_HISTORY_DIR are added as well
+    #[arg(long = "history-dir", value_name = "DIR", global = true)]
+    history_dirs: Vec<PathBuf>,
+
+    /// Also read history from this VS Code `--user-data-dir` (repeatable)
+    #[arg(long = "user-data-dir", value_name = "DIR", global = true)]
+    user_data_dirs: Vec<PathBuf>,
+
+    /// Only read the directories given explicitly, skip auto-discovery
+    #[arg(long, global = true)]
+    no_discover: bool,
+
+    /// Discover history under this home directory instead of the current user's
+    #[arg(long, value_name = "DIR", global = true)]
+    home: Option<PathBuf>,
+
+    /// Fail on problems in the history, such as unreadable `entries.json`
+    /// files or missing backups, instead of warning and skipping them
+    #[arg(long, global = true)]
+    strict: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -21,113 +86,1392 @@ struct Tardis {
 #[derive(Subcommand, Debug)]
 enum Command {
     /// List all vscode backup files in current directory
+    #[command(
+        after_help = timeexpr::HELP,
+        after_long_help = output::long_help(&[timeexpr::HELP, output::LIST_SCHEMA])
+    )]
     List {
-        #[arg(short, long)]
-        verbose: bool,
+        #[command(flatten)]
+        list: ListArgs,
+
+        /// Only list files that no longer exist on disk
+        #[arg(long)]
+        deleted: bool,
     },
+    /// List files that were deleted from disk but still have backups
+    #[command(
+        after_help = timeexpr::HELP,
+        after_long_help = output::long_help(&[timeexpr::HELP, output::LIST_SCHEMA])
+    )]
+    Deleted {
+        #[command(flatten)]
+        list: ListArgs,
+    },
+    /// Restore files to their most recent backup, or the one selected
+    #[command(after_help = timeexpr::HELP)]
     Restore {
-        /// The files to restore
-        #[arg()]
+        /// The files to restore, relative to --dir. A directory restores
+        /// every file with history below it, skipping those without the
+        /// selected backup
+        #[arg(required_unless_present_any = ["all", "deleted"], conflicts_with_all = ["all", "deleted"])]
         files: Vec<PathBuf>,
-    }
 
+        /// Restore every file with history under --dir
+        #[arg(long, conflicts_with = "deleted")]
+        all: bool,
+
+        /// Recreate every deleted file with history under --dir
+        #[arg(long)]
+        deleted: bool,
+
+        #[command(flatten)]
+        select: SelectArgs,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        #[command(flatten)]
+        confine: ConfineArgs,
+
+        #[command(flatten)]
+        force: ForceArgs,
+    },
+    /// Show changes between two backups, or between a backup and the working file
+    #[command(after_help = select::REV_HELP)]
+    Diff {
+        /// The file to compare, relative to --dir. A directory compares every
+        /// file with history below it
+        file: PathBuf,
+
+        /// With no revision the latest backup is compared to the working file,
+        /// with one that backup is, and with two they are compared to each other
+        #[arg(value_name = "REV", num_args = 0..=2)]
+        revs: Vec<String>,
+
+        /// When to color the output
+        #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+        color: ColorMode,
+
+        /// Number of unchanged lines to show around each change
+        #[arg(short = 'U', long, value_name = "N", default_value_t = 3)]
+        context: usize,
+
+        /// Only show the number of changed lines per file
+        #[arg(long)]
+        stat: bool,
+    },
+    /// Show which tracked files under --dir differ from their newest backup
+    #[command(
+        after_help = STATUS_HELP,
+        after_long_help = output::long_help(&[STATUS_HELP, output::STATUS_SCHEMA])
+    )]
+    Status {
+        /// Also list unchanged files
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Also list files under --dir that have no history, skipping hidden
+        /// files and directories and those ignored by `.gitignore`
+        #[arg(short, long)]
+        untracked: bool,
+
+        /// Output format. `--help` lists the fields
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Check the history store itself for missing backups, stray files and
+    /// unreadable folders. Looks at all history, not just --dir
+    #[command(
+        after_help = FSCK_HELP,
+        after_long_help = output::long_help(&[FSCK_HELP, output::FSCK_SCHEMA])
+    )]
+    Fsck {
+        /// Drop entries whose backup file is missing from `entries.json`,
+        /// after printing which ones
+        #[arg(long)]
+        repair: bool,
+
+        /// Output format. `--help` lists the fields
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// Collapse consecutive entries with identical content into the earliest
+    /// of them. Looks at all history, not just --dir
+    Dedup {
+        /// Also delete the backups of the collapsed entries. Otherwise they
+        /// are left unreferenced until `prune` deletes them
+        #[arg(long)]
+        delete_files: bool,
+
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// Show how much disk the history of the files under --dir uses
+    #[command(visible_alias = "stats", after_long_help = output::DU_SCHEMA)]
+    Du {
+        /// What to add up the usage by
+        #[arg(long, value_enum, default_value_t = du::GroupBy::Workspace)]
+        by: du::GroupBy,
+
+        /// Order of the output
+        #[arg(long, value_enum, default_value_t = du::SortBy::Size)]
+        sort: du::SortBy,
+
+        /// Only show the first N rows
+        #[arg(short = 'n', long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Cover every resource in the history, not just files under --dir
+        #[arg(long)]
+        global: bool,
+
+        /// Output format. `--help` lists the fields
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Delete old entries from the history store. Looks at all history, not
+    /// just --dir. Deleted backups are not journaled and cannot be undone
+    #[command(visible_alias = "gc")]
+    Prune {
+        /// Keep the newest N entries of each file, counting the history of
+        /// every editor flavor together
+        #[arg(long, value_name = "N")]
+        keep_last: Option<usize>,
+
+        /// Keep every entry newer than this, e.g. `30d` or `2 weeks`
+        #[arg(long, value_name = "DURATION", value_parser = timeexpr::parse_duration)]
+        keep_within: Option<TimeDelta>,
+
+        /// Keep the newest entry of each of the last N hours that have one
+        #[arg(long, value_name = "N")]
+        hourly: Option<usize>,
+
+        /// Keep the newest entry of each of the last N days that have one
+        #[arg(long, value_name = "N")]
+        daily: Option<usize>,
+
+        /// Keep the newest entry of each of the last N weeks that have one
+        #[arg(long, value_name = "N")]
+        weekly: Option<usize>,
+
+        /// Drop all history of files not below this directory (repeatable).
+        /// Untitled buffers and other resources that are not files are left
+        /// to the retention rules
+        #[arg(long = "root", value_name = "DIR")]
+        roots: Vec<PathBuf>,
+
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// Undo the most recent restore, snapshot or rollback
+    Undo {
+        /// Undo this operation from `journal` instead of the most recent one
+        #[arg(long)]
+        id: Option<String>,
+
+        #[command(flatten)]
+        write: WriteArgs,
+    },
+    /// List the operations recorded in the journal, most recent last
+    Journal {
+        /// Also list the files written by each operation
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Print a backup of a file to stdout
+    #[command(visible_alias = "cat", after_help = select::REV_HELP)]
+    Show {
+        /// The file relative to --dir, optionally followed by @REV. Without a
+        /// revision the latest backup is printed
+        #[arg(value_name = "FILE[@REV]")]
+        spec: String,
+    },
+    /// Show the backups of a file, newest first
+    #[command(
+        after_help = timeexpr::HELP,
+        after_long_help = output::long_help(&[timeexpr::HELP, output::LOG_SCHEMA])
+    )]
+    Log {
+        /// The file relative to --dir
+        file: PathBuf,
+
+        #[command(flatten)]
+        range: RangeArgs,
+
+        /// Only show the N most recent backups
+        #[arg(short = 'n', long = "max-count", value_name = "N")]
+        max_count: Option<usize>,
+
+        /// Output format. `--help` lists the fields
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Rebuild the tracked files under --dir as they were at a point in time
+    /// into a separate directory
+    #[command(after_help = timeexpr::HELP)]
+    Snapshot {
+        /// Use the newest backup of each file at or before this time
+        #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+        at: DateTime<Utc>,
+
+        /// Empty or new directory to write the snapshot into
+        #[arg(long, value_name = "DIR")]
+        out: PathBuf,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        #[command(flatten)]
+        confine: ConfineArgs,
+    },
+    /// Restore every tracked file under --dir to its version at a point in time
+    #[command(after_help = timeexpr::HELP)]
+    Rollback {
+        /// Use the newest backup of each file at or before this time
+        #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+        to: DateTime<Utc>,
+
+        /// Ask before rolling back each file
+        #[arg(short, long, conflicts_with = "yes")]
+        interactive: bool,
+
+        /// Skip files whose current content already matches
+        #[arg(long)]
+        only_changed: bool,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        #[command(flatten)]
+        confine: ConfineArgs,
+
+        #[command(flatten)]
+        force: ForceArgs,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CodeHistoryFile {
-    dir: PathBuf,
-    info: CodeHistoryInfo,
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
 }
 
-impl CodeHistoryFile {
-    fn current_file(&self) -> PathBuf {
-        PathBuf::from(self.info.resource.path())
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
     }
+}
 
-    fn backup_files(&self) -> Vec<(DateTime<Utc>, PathBuf)> {
-        self.info
-            .entries
-            .iter()
-            .map(|e| (e.timestamp.clone(), self.dir.join(&e.id)))
-            .collect()
-    }
+/// Options shared by the listing commands.
+#[derive(Args, Debug)]
+struct ListArgs {
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// List every resource with history, not just files under --dir,
+    /// grouped by project (the nearest directory with a `.git`, `Cargo.toml`,
+    /// `package.json` or similar)
+    #[arg(long)]
+    global: bool,
+
+    /// Output format. `--help` lists the fields
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    #[command(flatten)]
+    range: RangeArgs,
+}
+
+/// Options shared by the commands that write files. Each of them prints its
+/// plan first and asks for confirmation before writing.
+#[derive(Args, Debug)]
+struct WriteArgs {
+    /// Only print the plan, write nothing
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Write without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
 
-    fn is_scheme(&self, scheme: &str) -> bool {
-        self.info.resource.scheme() == scheme
+/// Safeguard against writing outside the directory a command writes to,
+/// --dir or --out.
+#[derive(Args, Debug)]
+struct ConfineArgs {
+    /// Write files even if they resolve to outside of --dir (--out for
+    /// snapshot), e.g. through a symlink. Refused by default as a safeguard
+    /// against crafted history
+    #[arg(long)]
+    allow_outside: bool,
+}
+
+/// Safeguard against overwriting changes that no backup holds.
+#[derive(Args, Debug)]
+struct ForceArgs {
+    /// Overwrite files even if they hold changes that are in none of their
+    /// backups
+    #[arg(long)]
+    force: bool,
+}
+
+/// Options choosing which backup of a file to use. Without any of them the
+/// most recent backup is used.
+#[derive(Args, Debug)]
+#[group(multiple = false)]
+struct SelectArgs {
+    /// Use the entry with this id, i.e. the backup's file name
+    #[arg(long, value_name = "ID")]
+    entry: Option<String>,
+
+    /// Use the newest entry before this time
+    #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+    before: Option<DateTime<Utc>>,
+
+    /// Use the newest entry at or before this time
+    #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+    at: Option<DateTime<Utc>>,
+
+    /// Use the entry N steps back from the most recent one (0 is the most recent)
+    #[arg(long, value_name = "N")]
+    nth_back: Option<usize>,
+}
+
+impl SelectArgs {
+    fn selector(&self) -> Selector {
+        if let Some(id) = &self.entry {
+            Selector::Entry(id.clone())
+        } else if let Some(time) = self.before {
+            Selector::Before(time)
+        } else if let Some(time) = self.at {
+            Selector::At(time)
+        } else if let Some(n) = self.nth_back {
+            Selector::NthBack(n)
+        } else {
+            Selector::Latest
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CodeHistoryInfo {
-    version: u32,
-    resource: url::Url,
-    entries: Vec<CodeHistoryEntry>,
+/// Options limiting the backups shown to a time range.
+#[derive(Args, Debug)]
+struct RangeArgs {
+    /// Only show backups made at or after this time
+    #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only show backups made at or before this time
+    #[arg(long, value_name = "TIME", value_parser = timeexpr::parse)]
+    until: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CodeHistoryEntry {
-    id: PathBuf,
-    #[serde(with = "ts_milliseconds")]
-    timestamp: DateTime<Utc>,
+impl RangeArgs {
+    fn is_set(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    fn contains(&self, time: &DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| *time >= since)
+            && self.until.is_none_or(|until| *time <= until)
+    }
 }
 
 fn main() -> Result<()> {
     let args: Tardis = Tardis::parse();
 
-
-    let home_dir = dirs::home_dir().ok_or_else(|| eyre!("Could not find home directory"))?;
-    let history_dir = home_dir.join(CODE_HISTORY_DIR);
-    let current_dir = args.dir.canonicalize().context("Could not find current directory")?;
-    let found_files = walkdir::WalkDir::new(history_dir)
-        .max_depth(3)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file() && e.path().ends_with("entries.json"))
-        .map(|e| {
-            let info = read_to_string(e.path())
-                .with_context(|| format!("Could not read file {:?}", e.path()))?;
-            let info: CodeHistoryInfo = serde_json::from_str(&info)?;
-            let file = CodeHistoryFile {
-                dir: e
-                    .path()
-                    .parent()
-                    .ok_or_else(|| eyre!("Could not find parent directory"))?
-                    .to_path_buf(),
-                    info,
+    let current_dir = args
+        .dir
+        .canonicalize()
+        .context("Could not find current directory")?;
+    let roots = history_roots(&args)?;
+    let mut scan = history::scan(&roots);
+    match &args.command {
+        Command::Fsck {
+            repair,
+            format,
+            write,
+        } => return run_fsck(scan, *repair, *format, write),
+        Command::Prune {
+            keep_last,
+            keep_within,
+            hourly,
+            daily,
+            weekly,
+            roots,
+            write,
+        } => {
+            scan.report(args.strict)?;
+            let roots = roots
+                .iter()
+                .map(|root| {
+                    let root = to_absolute(root, &current_dir);
+                    root.canonicalize().with_context(|| {
+                        format!("Could not find --root {}", root.to_string_lossy())
+                    })
+                })
+                .collect::<Result<_>>()?;
+            let policy = prune::Policy {
+                keep_last: *keep_last,
+                keep_within: *keep_within,
+                hourly: *hourly,
+                daily: *daily,
+                weekly: *weekly,
+                roots,
             };
-            if file.is_scheme("file") && file.current_file().starts_with(&current_dir) {
-                Ok(Some(file))
+            return run_prune(&scan.files, &policy, write);
+        }
+        Command::Dedup {
+            delete_files,
+            write,
+        } => {
+            scan.report(args.strict)?;
+            return run_dedup(&scan.files, *delete_files, write);
+        }
+        Command::List { list, deleted } if list.global => {
+            scan.report(args.strict)?;
+            return print_list(&scan.files, &current_dir, list, *deleted);
+        }
+        Command::Deleted { list } if list.global => {
+            scan.report(args.strict)?;
+            return print_list(&scan.files, &current_dir, list, true);
+        }
+        Command::Du {
+            by,
+            sort,
+            limit,
+            global: true,
+            format,
+        } => {
+            scan.report(args.strict)?;
+            return run_du(&scan.files, None, *by, *sort, *limit, *format);
+        }
+        _ => {}
+    }
+    scan.files
+        .retain(|file| file.is_file() && file.current_file().starts_with(&current_dir));
+    scan.check_backups();
+    scan.report(args.strict)?;
+    let found_files = scan.files;
+
+    match args.command {
+        Command::List { list, deleted } => {
+            print_list(&found_files, &current_dir, &list, deleted)?;
+        }
+        Command::Deleted { list } => {
+            print_list(&found_files, &current_dir, &list, true)?;
+        }
+        Command::Restore {
+            files,
+            all,
+            deleted,
+            select,
+            write,
+            confine,
+            force,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let selected = if all {
+                tracked.iter().collect()
+            } else if deleted {
+                tracked.iter().filter(|file| file.is_deleted()).collect()
             } else {
-                Ok(None)
+                select_files(&tracked, &files, &current_dir)?
+            };
+            let selector = select.selector();
+            let written = Journal::open()?.last_written()?;
+            // Only files named exactly must have a matching entry, files
+            // found through a directory are skipped like with --all.
+            let named: Vec<_> = files
+                .iter()
+                .map(|file| normalize(to_absolute(file, &current_dir)))
+                .collect();
+            let mut plan = Plan::new("restore");
+            let mut missing = Vec::new();
+            let mut skipped = Vec::new();
+            for file in selected {
+                let current_file = file.path.strip_prefix(&current_dir)?.to_string_lossy();
+                match selector.select(&file.backup_files()) {
+                    Some((ts, backup)) => {
+                        let mut action = Action::new(
+                            current_file.into_owned(),
+                            Source::Entry(*ts, backup.clone()),
+                            file.path.clone(),
+                        )?;
+                        action.unrecorded = action.differs
+                            && file.has_unrecorded_changes(
+                                written.get(&file.path).map(String::as_str),
+                            )?;
+                        plan.actions.push(action);
+                    }
+                    None if named.contains(&file.path) => missing.push(current_file.into_owned()),
+                    None => skipped.push(current_file.into_owned()),
+                }
+            }
+            if !missing.is_empty() {
+                return Err(eyre!("No {} for: {}", selector, missing.join(", ")));
+            }
+            if !skipped.is_empty() {
+                plan.notes.push(format!("No {}, skipped:", selector));
+                plan.notes
+                    .extend(skipped.iter().map(|name| format!("  {}", name)));
+            }
+            if !confine.allow_outside {
+                plan.confine(&current_dir)?;
+            }
+            if !write.dry_run {
+                plan.check_unrecorded(force.force)?;
+            }
+            if plan::confirm(&plan, write.dry_run, write.yes)? {
+                plan.apply(&Journal::open()?)?;
+            }
+        }
+        Command::Diff {
+            file,
+            revs,
+            color,
+            context,
+            stat,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let selected = select_files(&tracked, std::slice::from_ref(&file), &current_dir)?;
+            let mut stats = Vec::new();
+            for file in selected {
+                let name = file.path.strip_prefix(&current_dir)?.to_string_lossy();
+                let backups = file.backup_files();
+                let backup = |rev: Option<&String>, side: &str| -> Result<Version> {
+                    let selector = match rev {
+                        Some(rev) => Selector::from_rev(rev)?,
+                        None => Selector::Latest,
+                    };
+                    let (ts, path) = selector
+                        .select(&backups)
+                        .ok_or_else(|| eyre!("No {} for {}", selector, name))?;
+                    Version::backup(side, &name, ts, path)
+                };
+                let (old, new) = match revs.as_slice() {
+                    [] => (backup(None, "a")?, Version::working(&name, &file.path)?),
+                    [rev] => (
+                        backup(Some(rev), "a")?,
+                        Version::working(&name, &file.path)?,
+                    ),
+                    [old, new, ..] => (backup(Some(old), "a")?, backup(Some(new), "b")?),
+                };
+                if diff::is_binary(&old.content) || diff::is_binary(&new.content) {
+                    if old.content != new.content {
+                        println!("Binary files {} and {} differ", old.label, new.label);
+                    }
+                    continue;
+                }
+                let (old_text, new_text) = (old.text(), new.text());
+                if stat {
+                    stats.push((name.into_owned(), diff::stat(&old_text, &new_text)));
+                } else {
+                    print!(
+                        "{}",
+                        diff::unified(
+                            &old.label,
+                            &new.label,
+                            &old_text,
+                            &new_text,
+                            context,
+                            color.enabled()
+                        )
+                    );
+                }
+            }
+            if stat {
+                print_stat(&stats, color.enabled());
+            }
+        }
+        Command::Snapshot {
+            at,
+            out,
+            write,
+            confine,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let mut plan = snapshot::plan(&tracked, &current_dir, at, &out)?;
+            if !confine.allow_outside {
+                plan.confine(&out)?;
+            }
+            if plan::confirm(&plan, write.dry_run, write.yes)? {
+                plan.apply(&Journal::open()?)?;
+            }
+        }
+        Command::Rollback {
+            to,
+            interactive,
+            only_changed,
+            write,
+            confine,
+            force,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let written = Journal::open()?.last_written()?;
+            let mut plan = rollback::plan(&tracked, &current_dir, to, only_changed, &written)?;
+            if !confine.allow_outside {
+                plan.confine(&current_dir)?;
+            }
+            if !write.dry_run {
+                plan.check_unrecorded(force.force)?;
+            }
+            if interactive {
+                plan.print();
+                if !write.dry_run {
+                    plan.apply_interactive(&Journal::open()?)?;
+                }
+            } else if plan::confirm(&plan, write.dry_run, write.yes)? {
+                plan.apply(&Journal::open()?)?;
             }
+        }
+        Command::Status {
+            verbose,
+            untracked,
+            format,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let records = status::status(&tracked, &current_dir, untracked)?;
+            match format {
+                Format::Text => {
+                    for record in &records {
+                        if verbose || record.state != State::Unchanged {
+                            let label = format!("{}:", record.state.label());
+                            println!("{:<16} {}", label, record.path.to_string_lossy());
+                        }
+                    }
+                    let unchanged = records
+                        .iter()
+                        .filter(|record| record.state == State::Unchanged)
+                        .count();
+                    println!(
+                        "{} changed, {} unchanged",
+                        records.len() - unchanged,
+                        unchanged
+                    );
+                }
+                Format::Json | Format::Ndjson => output::print_json(&records, format)?,
+                Format::Csv => output::print_csv(&output::STATUS_CSV_COLUMNS, &records)?,
+            }
+            if records
+                .iter()
+                .any(|record| record.state != State::Unchanged)
+            {
+                std::process::exit(EXIT_DIRTY);
+            }
+        }
+        Command::Du {
+            by,
+            sort,
+            limit,
+            global: false,
+            format,
+        } => {
+            run_du(&found_files, Some(&current_dir), by, sort, limit, format)?;
+        }
+        Command::Fsck { .. }
+        | Command::Prune { .. }
+        | Command::Dedup { .. }
+        | Command::Du { global: true, .. } => {
+            unreachable!("handled before filtering the scan")
+        }
+        Command::Undo { id, write } => {
+            let journal = Journal::open()?;
+            let operation = journal.undoable(id.as_deref())?;
+            let plan = journal.undo_plan(&operation)?;
+            if plan::confirm(&plan, write.dry_run, write.yes)? {
+                plan.apply(&journal)?;
+                journal.mark_undone(operation)?;
+            }
+        }
+        Command::Journal { verbose } => {
+            for operation in Journal::open()?.operations()? {
+                println!(
+                    "{}  {}  {:<9} {} file{}{}",
+                    operation.id,
+                    operation
+                        .time
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M:%S"),
+                    operation.operation,
+                    operation.files.len(),
+                    if operation.files.len() == 1 { "" } else { "s" },
+                    if operation.undone { "  (undone)" } else { "" }
+                );
+                if verbose {
+                    for file in &operation.files {
+                        let state = if file.saved.is_some() {
+                            "saved"
+                        } else {
+                            "created"
+                        };
+                        println!("    {:<8} {}", state, file.target.to_string_lossy());
+                    }
+                }
+            }
+        }
+        Command::Show { spec } => {
+            let tracked = history::tracked_files(&found_files);
+            let (file, selector) = parse_show_spec(&tracked, &spec, &current_dir)?;
+            let backups = file.backup_files();
+            let (_, backup) = selector
+                .select(&backups)
+                .ok_or_else(|| eyre!("No {} for {}", selector, file.path.to_string_lossy()))?;
+            let content = std::fs::read(backup)
+                .with_context(|| format!("Could not read backup {}", backup.to_string_lossy()))?;
+            std::io::stdout().lock().write_all(&content)?;
+        }
+        Command::Log {
+            file,
+            range,
+            max_count,
+            format,
+        } => {
+            let tracked = history::tracked_files(&found_files);
+            let file = find_file(&tracked, &file, &current_dir)?;
+            let now = Utc::now();
+            let mut entries = log_entries(file);
+            entries.retain(|entry| range.contains(&entry.timestamp));
+            entries.reverse();
+            entries.truncate(max_count.unwrap_or(usize::MAX));
+            match format {
+                Format::Text => {
+                    for entry in entries {
+                        let delta = match (entry.same_as, entry.insertions, entry.deletions) {
+                            (Some(same_as), _, _) => format!("= #{}", same_as),
+                            (None, Some(insertions), Some(deletions)) => {
+                                format!("+{} -{}", insertions, deletions)
+                            }
+                            _ => String::new(),
+                        };
+                        let (size, lines) = match (entry.size, entry.lines) {
+                            (Some(size), Some(lines)) => {
+                                (human_size(size), format!("{} lines", lines))
+                            }
+                            _ => ("missing".to_string(), String::new()),
+                        };
+                        let line = format!(
+                            "{:>4}  {}  {:<16}  {:<12}  {:>9}  {:>10}  {}",
+                            entry.index,
+                            entry.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                            timeexpr::format_age(entry.time, now),
+                            entry.id,
+                            size,
+                            lines,
+                            delta
+                        );
+                        println!("{}", line.trim_end());
+                    }
+                }
+                Format::Json | Format::Ndjson => output::print_json(&entries, format)?,
+                Format::Csv => output::print_csv(&output::LOG_CSV_COLUMNS, &entries)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe every backup of `file`, oldest first.
+fn log_entries(file: &TrackedFile) -> Vec<LogRecord> {
+    let mut previous: Option<String> = None;
+    let mut entries: Vec<LogRecord> = Vec::new();
+    for (index, (timestamp, path)) in file.backup_files().into_iter().enumerate() {
+        let bytes = std::fs::read(&path).ok();
+        let sha256 = bytes.as_deref().map(hash::sha256);
+        let same_as = match entries.last() {
+            Some(last) if sha256.is_some() && last.sha256 == sha256 => {
+                Some(last.same_as.unwrap_or(last.index))
+            }
+            _ => None,
+        };
+        let text = bytes
+            .as_ref()
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+        let delta = match (&previous, &text) {
+            (Some(previous), Some(text)) => Some(diff::stat(previous, text)),
+            _ => None,
+        };
+        let lines = text.as_ref().map(|text| text.lines().count());
+        if text.is_some() {
+            previous = text;
+        }
+        entries.push(LogRecord {
+            index,
+            id: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            timestamp,
+            time: timestamp,
+            size: bytes.map(|bytes| bytes.len() as u64),
+            lines,
+            insertions: delta.map(|d| d.insertions),
+            deletions: delta.map(|d| d.deletions),
+            sha256,
+            same_as,
+            backup: path,
+        });
+    }
+    entries
+}
+
+/// Format a byte count with a binary unit, e.g. `1.5 KiB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// One side of a diff.
+struct Version {
+    label: String,
+    content: Vec<u8>,
+}
+
+impl Version {
+    /// A backup, labelled `a/` as the old side of a diff or `b/` as the new.
+    fn backup(side: &str, name: &str, ts: &DateTime<Utc>, path: &Path) -> Result<Self> {
+        let content = std::fs::read(path)
+            .with_context(|| format!("Could not read backup {}", path.to_string_lossy()))?;
+        let id = path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(Version {
+            label: format!("{}/{}\t{} ({})", side, name, ts, id),
+            content,
         })
-        .filter_map(|e| e.transpose())
-        .collect::<Result<Vec<_>>>()?;
+    }
 
-    match args.command {
-        Command::List { verbose } => {
-            for file in found_files {
-                let current_file = file.current_file().strip_prefix(&current_dir)?.to_path_buf();
-                if verbose {
-                    for (ts, backup) in file.backup_files() {
-                        println!("{}\t{}\t{}", current_file.to_string_lossy(), ts, backup.to_string_lossy());
+    /// The file on disk, or an empty `/dev/null` if it was deleted.
+    fn working(name: &str, path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Version {
+                label: "/dev/null".to_string(),
+                content: Vec::new(),
+            });
+        }
+        let content = std::fs::read(path)
+            .with_context(|| format!("Could not read {}", path.to_string_lossy()))?;
+        Ok(Version {
+            label: format!("b/{}\tworking copy", name),
+            content,
+        })
+    }
+
+    fn text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.content)
+    }
+}
+
+/// Print a `git diff --stat` style summary.
+fn print_stat(stats: &[(String, diff::Stat)], color: bool) {
+    const MAX_BAR: usize = 50;
+    let changed: Vec<_> = stats.iter().filter(|(_, s)| s.changed() > 0).collect();
+    let width = changed
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let most = changed.iter().map(|(_, s)| s.changed()).max().unwrap_or(0);
+    let (green, red, reset) = if color {
+        ("\x1b[32m", "\x1b[31m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+    let mut total = diff::Stat::default();
+    for (name, stat) in &changed {
+        let scale = |n: usize| {
+            if most <= MAX_BAR {
+                n
+            } else {
+                (n * MAX_BAR).div_ceil(most)
+            }
+        };
+        println!(
+            " {:width$} | {:>5} {}{}{}{}{}",
+            name,
+            stat.changed(),
+            green,
+            "+".repeat(scale(stat.insertions)),
+            red,
+            "-".repeat(scale(stat.deletions)),
+            reset,
+        );
+        total.insertions += stat.insertions;
+        total.deletions += stat.deletions;
+    }
+    println!(
+        " {} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+        changed.len(),
+        if changed.len() == 1 { "" } else { "s" },
+        total.insertions,
+        if total.insertions == 1 { "" } else { "s" },
+        total.deletions,
+        if total.deletions == 1 { "" } else { "s" },
+    );
+}
+
+fn run_fsck(scan: history::Scan, repair: bool, format: Format, write: &WriteArgs) -> Result<()> {
+    let mut records = fsck::check(&scan, Utc::now())?;
+    let repairs = fsck::repairs(&scan);
+    if repair && !repairs.is_empty() {
+        // The plan goes to stderr when stdout carries machine readable output.
+        let mut out: Box<dyn Write> = match format {
+            Format::Text => Box::new(std::io::stdout()),
+            _ => Box::new(std::io::stderr()),
+        };
+        let dropped: usize = repairs.iter().map(|r| r.dropped().count()).sum();
+        writeln!(
+            out,
+            "Plan for repair: drop {} entries without a backup in {} history folders",
+            dropped,
+            repairs.len()
+        )?;
+        for repair in &repairs {
+            for entry in repair.dropped() {
+                writeln!(
+                    out,
+                    "  {}  {}",
+                    repair.file.dir.to_string_lossy(),
+                    entry.id.to_string_lossy()
+                )?;
+            }
+        }
+        if plan::proceed("repair", write.dry_run, write.yes)? {
+            for repair in &repairs {
+                repair.apply(&mut records)?;
+            }
+        }
+    }
+    match format {
+        Format::Text => {
+            for record in &records {
+                let line = format!(
+                    "{:<16}  {}: {}  {}",
+                    record.check.label(),
+                    record.path.to_string_lossy(),
+                    record.detail,
+                    if record.repaired { "(repaired)" } else { "" }
+                );
+                println!("{}", line.trim_end());
+            }
+            let repaired = records.iter().filter(|record| record.repaired).count();
+            println!(
+                "{} problems in {} history folders, {} repaired",
+                records.len(),
+                scan.files.len(),
+                repaired
+            );
+        }
+        Format::Json | Format::Ndjson => output::print_json(&records, format)?,
+        Format::Csv => output::print_csv(&output::FSCK_CSV_COLUMNS, &records)?,
+    }
+    if records.iter().any(|record| !record.repaired) {
+        std::process::exit(EXIT_DIRTY);
+    }
+    Ok(())
+}
+
+fn run_prune(files: &[CodeHistoryFile], policy: &prune::Policy, write: &WriteArgs) -> Result<()> {
+    let prunes = prune::plan(files, policy, &Local::now())?;
+    let dropped: usize = prunes.iter().map(|p| p.dropped()).sum();
+    let entries: usize = prunes.iter().map(|p| p.file.info.entries.len()).sum();
+    let deleted: usize = prunes.iter().map(|p| p.delete.len()).sum();
+    let bytes = prunes.iter().map(|p| p.bytes).sum();
+    println!(
+        "Plan for prune: drop {} of {} entries in {} history folders, delete {} files ({})",
+        dropped,
+        entries,
+        prunes.len(),
+        deleted,
+        human_size(bytes)
+    );
+    let names: Vec<_> = prunes
+        .iter()
+        .map(|p| p.file.current_file().to_string_lossy().into_owned())
+        .collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    for (prune, name) in prunes.iter().zip(&names) {
+        let keep = match prune.keep.len() {
+            0 => "remove all".to_string(),
+            kept => format!("keep {} of {}", kept, prune.file.info.entries.len()),
+        };
+        println!(
+            "  {:width$}  {:<14}  delete {:>3} file{:<1}  {:>10}",
+            name,
+            keep,
+            prune.delete.len(),
+            if prune.delete.len() == 1 { "" } else { "s" },
+            human_size(prune.bytes)
+        );
+    }
+    if prunes.is_empty() || !plan::proceed("prune", write.dry_run, write.yes)? {
+        return Ok(());
+    }
+    for prune in &prunes {
+        prune.apply()?;
+    }
+    println!("Deleted {} files ({})", deleted, human_size(bytes));
+    Ok(())
+}
+
+fn run_dedup(files: &[CodeHistoryFile], delete_files: bool, write: &WriteArgs) -> Result<()> {
+    let dedups = dedup::plan(files)?;
+    let dropped: usize = dedups.iter().map(|d| d.drop.len()).sum();
+    let bytes = dedups.iter().map(|d| d.bytes).sum();
+    println!(
+        "Plan for dedup: collapse {} identical entries in {} history folders ({}{})",
+        dropped,
+        dedups.len(),
+        human_size(bytes),
+        if delete_files {
+            " to delete"
+        } else {
+            " left unreferenced"
+        }
+    );
+    let names: Vec<_> = dedups
+        .iter()
+        .map(|d| d.file.current_file().to_string_lossy().into_owned())
+        .collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    for (dedup, name) in dedups.iter().zip(&names) {
+        println!(
+            "  {:width$}  {:>4} of {:<4} entries  {:>10}",
+            name,
+            dedup.drop.len(),
+            dedup.file.info.entries.len(),
+            human_size(dedup.bytes)
+        );
+    }
+    if dedups.is_empty() || !plan::proceed("dedup", write.dry_run, write.yes)? {
+        return Ok(());
+    }
+    for dedup in &dedups {
+        dedup.apply(delete_files)?;
+    }
+    println!("Collapsed {} entries", dropped);
+    Ok(())
+}
+
+fn run_du(
+    files: &[CodeHistoryFile],
+    base: Option<&Path>,
+    by: du::GroupBy,
+    sort: du::SortBy,
+    limit: Option<usize>,
+    format: Format,
+) -> Result<()> {
+    let mut records = du::usage(files, base, by)?;
+    let total = (
+        records.iter().map(|r| r.size).sum(),
+        records.iter().map(|r| r.folders).sum::<usize>(),
+        records.iter().map(|r| r.entries).sum::<usize>(),
+    );
+    du::sort(&mut records, sort);
+    records.truncate(limit.unwrap_or(usize::MAX));
+    match format {
+        Format::Text => {
+            let date = |time: Option<DateTime<Utc>>| match time {
+                Some(time) => time.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+                None => "-".to_string(),
+            };
+            println!(
+                "{:>10}  {:>7}  {:>7}  {:<10}  {:<10}  NAME",
+                "SIZE", "FILES", "ENTRIES", "OLDEST", "NEWEST"
+            );
+            for record in &records {
+                println!(
+                    "{:>10}  {:>7}  {:>7}  {:<10}  {:<10}  {}",
+                    human_size(record.size),
+                    record.folders,
+                    record.entries,
+                    date(record.oldest),
+                    date(record.newest),
+                    record.name
+                );
+            }
+            println!(
+                "{:>10}  {:>7}  {:>7}  total",
+                human_size(total.0),
+                total.1,
+                total.2
+            );
+        }
+        Format::Json | Format::Ndjson => output::print_json(&records, format)?,
+        Format::Csv => output::print_csv(&output::DU_CSV_COLUMNS, &records)?,
+    }
+    Ok(())
+}
+
+/// Print the history folders under `current_dir` for `list` and `deleted`,
+/// or with `--global` all of them grouped by project, most recently active
+/// project first.
+fn print_list(
+    found_files: &[CodeHistoryFile],
+    current_dir: &Path,
+    list: &ListArgs,
+    deleted: bool,
+) -> Result<()> {
+    let mut records = Vec::new();
+    for file in found_files {
+        if deleted && !(file.is_file() && file.is_deleted()) {
+            continue;
+        }
+        let filter = |e: &history::CodeHistoryEntry| list.range.contains(&e.timestamp);
+        let record = if !list.global {
+            let current_file = file.current_file().strip_prefix(current_dir)?.to_path_buf();
+            ListRecord::new(current_file, file, filter)
+        } else if file.is_file() {
+            let current_file = file.current_file();
+            let project = workspace::root(&current_file);
+            let path = current_file.strip_prefix(&project)?.to_path_buf();
+            let mut record = ListRecord::new(path, file, filter);
+            record.project = Some(project);
+            record
+        } else {
+            ListRecord::new(PathBuf::from(file.info.resource.as_str()), file, filter)
+        };
+        if record.entries.is_empty() && list.range.is_set() {
+            continue;
+        }
+        records.push((file, record));
+    }
+
+    // Number of files and last activity per project.
+    let mut projects: HashMap<String, (usize, Option<DateTime<Utc>>)> = HashMap::new();
+    if list.global {
+        for (_, record) in &records {
+            let project = projects.entry(project_name(record)).or_default();
+            project.0 += 1;
+            project.1 = project.1.max(record.last_activity);
+        }
+        records.sort_by_cached_key(|(_, record)| {
+            let name = project_name(record);
+            (Reverse(projects[&name].1), name, record.path.clone())
+        });
+    }
+
+    match list.format {
+        Format::Text => {
+            let now = Utc::now();
+            let activity = |time: Option<DateTime<Utc>>| match time {
+                Some(time) => format!(
+                    "last activity {}, {}",
+                    time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                    timeexpr::format_age(time, now)
+                ),
+                None => "no activity".to_string(),
+            };
+            let mut current_project = None;
+            for (file, record) in &records {
+                let current_file = record.path.to_string_lossy();
+                let indent = if list.global { "  " } else { "" };
+                if list.global {
+                    let name = project_name(record);
+                    if current_project.as_ref() != Some(&name) {
+                        let (count, last) = projects[&name];
+                        println!(
+                            "{} ({} file{}, {})",
+                            name,
+                            count,
+                            if count == 1 { "" } else { "s" },
+                            activity(last)
+                        );
+                        current_project = Some(name);
+                    }
+                }
+                if list.verbose {
+                    let origin = file.origin();
+                    for entry in &record.entries {
+                        println!(
+                            "{}{}\t{}\t{}\t{}",
+                            indent,
+                            current_file,
+                            entry.time,
+                            entry.backup.to_string_lossy(),
+                            origin
+                        );
                     }
+                } else if list.global {
+                    println!(
+                        "{}{} ({} backups, {})",
+                        indent,
+                        current_file,
+                        record.entries.len(),
+                        activity(record.last_activity)
+                    );
                 } else {
-                    println!("{} ({} backups)", current_file.to_string_lossy(), file.backup_files().len());
+                    println!("{} ({} backups)", current_file, record.entries.len());
                 }
             }
         }
-        Command::Restore { files: _  } => {
-            for history_file in found_files {
-                let current_file = history_file.current_file().strip_prefix(&current_dir)?.to_path_buf();
-                let (ts, backup_file) = history_file.backup_files().last().cloned().ok_or_else(|| eyre!("No backup files found"))?;
-                println!("Restoring {} using {} from {}", current_file.to_string_lossy(), backup_file.to_string_lossy(), ts);
-                std::fs::copy(backup_file, current_file)?;
+        Format::Json | Format::Ndjson => {
+            let records: Vec<_> = records.into_iter().map(|(_, r)| r).collect();
+            output::print_json(&records, list.format)?;
+        }
+        Format::Csv => {
+            let mut rows = Vec::new();
+            for (_, record) in &records {
+                rows.extend(record.rows()?);
+            }
+            output::print_csv(&output::LIST_CSV_COLUMNS, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// The project a record is grouped under by `list --global`: the project
+/// directory, or the URI scheme for resources that are not files.
+fn project_name(record: &ListRecord) -> String {
+    match &record.project {
+        Some(project) => project.to_string_lossy().into_owned(),
+        None => format!("{}:", record.resource.scheme()),
+    }
+}
+
+/// Collect the history directories to scan from the command line, the
+/// environment and, unless disabled, auto-discovery.
+fn history_roots(args: &Tardis) -> Result<Vec<HistoryRoot>> {
+    let mut roots = Vec::new();
+    for dir in &args.history_dirs {
+        roots.push(HistoryRoot::explicit(dir.clone())?);
+    }
+    if let Some(dirs) = std::env::var_os(HISTORY_DIR_ENV) {
+        for dir in std::env::split_paths(&dirs).filter(|d| !d.as_os_str().is_empty()) {
+            roots.push(
+                HistoryRoot::explicit(dir)
+                    .with_context(|| format!("Invalid directory in ${}", HISTORY_DIR_ENV))?,
+            );
+        }
+    }
+    for dir in &args.user_data_dirs {
+        roots.push(HistoryRoot::user_data_dir(dir)?);
+    }
+    if !args.no_discover {
+        let home_dir = match &args.home {
+            Some(home) => home.clone(),
+            None => dirs::home_dir().ok_or_else(|| eyre!("Could not find home directory"))?,
+        };
+        roots.extend(roots::discover(&home_dir, args.home.is_none()));
+    }
+    // The same directory can be given explicitly and be discovered too, and
+    // reading it twice would list and prune every folder twice. The first
+    // occurrence is kept, taking the flavor of a later one if it has none.
+    let mut unique: Vec<(PathBuf, HistoryRoot)> = Vec::new();
+    for root in roots {
+        let canonical = root
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| root.path.clone());
+        match unique.iter_mut().find(|(seen, _)| *seen == canonical) {
+            Some((_, first)) => first.flavor = first.flavor.or(root.flavor),
+            None => unique.push((canonical, root)),
+        }
+    }
+    if unique.is_empty() {
+        return Err(eyre!("No history directories to read"));
+    }
+    Ok(unique.into_iter().map(|(_, root)| root).collect())
+}
+
+/// Split a `FILE[@REV]` argument into the tracked file and the selected
+/// backup. A file whose name contains `@` is matched as a whole first.
+fn parse_show_spec<'a, 'b>(
+    tracked: &'a [TrackedFile<'b>],
+    spec: &str,
+    dir: &Path,
+) -> Result<(&'a TrackedFile<'b>, Selector)> {
+    let whole = normalize(to_absolute(spec, dir));
+    if let Some(file) = tracked.iter().find(|file| file.path == whole) {
+        return Ok((file, Selector::Latest));
+    }
+    let (path, selector) = match spec.rsplit_once('@') {
+        Some((path, rev)) => (path, Selector::from_rev(rev)?),
+        None => (spec, Selector::Latest),
+    };
+    Ok((find_file(tracked, Path::new(path), dir)?, selector))
+}
+
+/// Find the tracked file named by `arg`, which is resolved against `dir`.
+fn find_file<'a, 'b>(
+    tracked: &'a [TrackedFile<'b>],
+    arg: &Path,
+    dir: &Path,
+) -> Result<&'a TrackedFile<'b>> {
+    let path = normalize(to_absolute(arg, dir));
+    tracked
+        .iter()
+        .find(|file| file.path == path)
+        .ok_or_else(|| eyre!("No history found for file {}", arg.to_string_lossy()))
+}
+
+/// Find the tracked files named by `args`, which are resolved against `dir`.
+///
+/// A directory argument selects every tracked file below it. Fails if any
+/// argument matches nothing, so a typo never goes unnoticed.
+fn select_files<'a, 'b>(
+    tracked: &'a [TrackedFile<'b>],
+    args: &[PathBuf],
+    dir: &Path,
+) -> Result<Vec<&'a TrackedFile<'b>>> {
+    let mut selected: Vec<&TrackedFile> = Vec::new();
+    let mut unknown = Vec::new();
+    for arg in args {
+        let path = normalize(to_absolute(arg, dir));
+        let matches: Vec<_> = tracked
+            .iter()
+            .filter(|file| file.path.starts_with(&path))
+            .collect();
+        if matches.is_empty() {
+            unknown.push(arg.to_string_lossy().into_owned());
+        }
+        for file in matches {
+            if !selected.iter().any(|s| s.path == file.path) {
+                selected.push(file);
             }
         }
     }
+    if !unknown.is_empty() {
+        return Err(eyre!(
+            "No history found under {} for: {}",
+            dir.to_string_lossy(),
+            unknown.join(", ")
+        ));
+    }
+    Ok(selected)
+}
 
-    Ok(())
+/// Resolve `.` and `..` components without touching the file system, so that
+/// paths of deleted files can still be matched.
+fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
 }
 
 fn to_absolute<P: AsRef<Path>, C: AsRef<Path>>(path: P, current_dir: C) -> PathBuf {
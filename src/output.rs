@@ -0,0 +1,436 @@
+//! Machine readable output for the listing commands.
+//!
+//! JSON output is an array of records, NDJSON one record per line and CSV a
+//! header followed by one row per record. The field names are kept stable,
+//! and described to users by the `*_SCHEMA` texts shown with `--help`. Timestamps are given both as
+//! milliseconds since the Unix epoch (`timestamp`, as in `entries.json`) and
+//! as RFC 3339 in UTC (`time`).
+
+use chrono::serde::ts_milliseconds;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use eyre::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::history::{CodeHistoryEntry, CodeHistoryFile};
+use crate::roots::Flavor;
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable text
+    #[default]
+    Text,
+    /// A JSON array of records
+    Json,
+    /// One JSON record per line
+    Ndjson,
+    /// Comma separated values with a header row
+    Csv,
+}
+
+/// A history folder, as output by `list`.
+#[derive(Debug, Serialize)]
+pub struct ListRecord {
+    /// Path of the file relative to --dir, or with `--global` relative to
+    /// its project. For resources that are not files, the URI.
+    pub path: PathBuf,
+    /// Absolute path of the file.
+    pub file: PathBuf,
+    /// Editor that recorded the history (`code`, `cursor`, ...), or null.
+    pub flavor: Option<Flavor>,
+    /// Remote authority such as `ssh-remote+devbox`, or null for local files.
+    pub remote: Option<String>,
+    /// The history folder containing `entries.json`.
+    pub history_dir: PathBuf,
+    /// The resource URI recorded by the editor.
+    pub resource: url::Url,
+    /// The project the file belongs to with `--global`, null otherwise and
+    /// for resources that are not files.
+    pub project: Option<PathBuf>,
+    /// Time of the most recent backup, or null if there are none.
+    pub last_activity: Option<DateTime<Utc>>,
+    /// Backups, oldest first.
+    pub entries: Vec<EntryRecord>,
+}
+
+/// A single backup within a [`ListRecord`].
+#[derive(Debug, Serialize)]
+pub struct EntryRecord {
+    /// Entry id, which is also the backup's file name.
+    pub id: String,
+    #[serde(with = "ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+    pub time: DateTime<Utc>,
+    /// Absolute path of the backup file.
+    pub backup: PathBuf,
+}
+
+/// CSV columns for `list`, one row per backup.
+pub static LIST_CSV_COLUMNS: [&str; 9] = [
+    "path",
+    "project",
+    "flavor",
+    "remote",
+    "history_dir",
+    "id",
+    "timestamp",
+    "time",
+    "backup",
+];
+
+impl ListRecord {
+    pub fn new(
+        path: PathBuf,
+        file: &CodeHistoryFile,
+        filter: impl Fn(&CodeHistoryEntry) -> bool,
+    ) -> Self {
+        ListRecord {
+            path,
+            file: file.current_file(),
+            flavor: file.flavor,
+            remote: file.remote_authority(),
+            history_dir: file.dir.clone(),
+            resource: file.info.resource.clone(),
+            project: None,
+            last_activity: file
+                .info
+                .entries
+                .iter()
+                .filter(|entry| filter(entry))
+                .map(|entry| entry.timestamp)
+                .max(),
+            entries: file
+                .info
+                .entries
+                .iter()
+                .filter(|entry| filter(entry))
+                .map(|entry| EntryRecord {
+                    id: entry.id.to_string_lossy().into_owned(),
+                    timestamp: entry.timestamp,
+                    time: entry.timestamp,
+                    backup: file.dir.join(&entry.id),
+                })
+                .collect(),
+        }
+    }
+
+    /// Flatten into one CSV row per backup, see [`LIST_CSV_COLUMNS`].
+    pub fn rows(&self) -> Result<Vec<Value>> {
+        let mut rows = Vec::new();
+        for entry in &self.entries {
+            let mut row = serde_json::to_value(self)?;
+            let entry = serde_json::to_value(entry)?;
+            if let (Some(row), Some(entry)) = (row.as_object_mut(), entry.as_object()) {
+                row.extend(entry.clone());
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+/// The fields of [`ListRecord`] for `--help`.
+pub static LIST_SCHEMA: &str = "\
+Fields with --format json or ndjson, one record per history folder:
+  path           string   file relative to --dir, or to its project with
+                          --global; the URI for resources that are not files
+  file           string   absolute path of the file
+  flavor         string   editor that recorded the history: code,
+                          code-insiders, vscodium, code-oss, cursor or
+                          windsurf; null if unknown
+  remote         string   remote authority such as ssh-remote+devbox; null for
+                          local files
+  history_dir    string   the history folder containing entries.json
+  resource       string   the resource URI recorded by the editor
+  project        string   the file's project with --global, otherwise null
+  last_activity  string   time of the most recent backup as RFC 3339 in UTC;
+                          null if none
+  entries        array    backups, oldest first, each with:
+    id           string   entry id, which is also the backup's file name
+    timestamp    integer  milliseconds since the Unix epoch
+    time         string   the same time as RFC 3339 in UTC
+    backup       string   absolute path of the backup file
+
+--format csv has one row per backup with the columns path, project, flavor,
+remote, history_dir, id, timestamp, time and backup.";
+
+/// A backup, as output by `log`.
+#[derive(Debug, Serialize)]
+pub struct LogRecord {
+    /// Position among the file's backups, oldest first. Usable as a revision.
+    pub index: usize,
+    /// Entry id, which is also the backup's file name.
+    pub id: String,
+    #[serde(with = "ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+    pub time: DateTime<Utc>,
+    /// Size in bytes, or null if the backup could not be read.
+    pub size: Option<u64>,
+    /// Number of lines, or null if the backup could not be read.
+    pub lines: Option<usize>,
+    /// Lines added since the previous readable backup, null for the first.
+    pub insertions: Option<usize>,
+    /// Lines removed since the previous readable backup, null for the first.
+    pub deletions: Option<usize>,
+    /// SHA-256 of the backup, or null if it could not be read.
+    pub sha256: Option<String>,
+    /// Index of the earliest backup in a run of identical ones, or null if
+    /// this backup differs from the one before. `dedup` collapses such runs.
+    pub same_as: Option<usize>,
+    /// Absolute path of the backup file.
+    pub backup: PathBuf,
+}
+
+/// CSV columns for `log`.
+pub static LOG_CSV_COLUMNS: [&str; 11] = [
+    "index",
+    "id",
+    "timestamp",
+    "time",
+    "size",
+    "lines",
+    "insertions",
+    "deletions",
+    "sha256",
+    "same_as",
+    "backup",
+];
+
+/// The fields of [`LogRecord`] for `--help`.
+pub static LOG_SCHEMA: &str = "\
+Fields with --format json, ndjson or csv, one record per backup:
+  index       integer  position among the file's backups, oldest first;
+                       usable as a revision
+  id          string   entry id, which is also the backup's file name
+  timestamp   integer  milliseconds since the Unix epoch
+  time        string   the same time as RFC 3339 in UTC
+  size        integer  size in bytes; null if the backup could not be read
+  lines       integer  number of lines; null if the backup could not be read
+  insertions  integer  lines added since the previous backup; null for the
+                       first
+  deletions   integer  lines removed since the previous backup; null for the
+                       first
+  sha256      string   SHA-256 of the backup; null if it could not be read
+  same_as     integer  index of the earliest backup in a run of identical
+                       ones; null if it differs from the one before
+  backup      string   absolute path of the backup file";
+
+/// How a working file compares to its newest backup, as output by `status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    /// The working file matches the newest backup.
+    Unchanged,
+    /// The working file differs from the newest backup.
+    Modified,
+    /// The working file no longer exists.
+    Deleted,
+    /// The file has no backup at all.
+    NeverRecorded,
+}
+
+impl State {
+    pub fn label(&self) -> &'static str {
+        match self {
+            State::Unchanged => "unchanged",
+            State::Modified => "modified",
+            State::Deleted => "deleted",
+            State::NeverRecorded => "never recorded",
+        }
+    }
+}
+
+/// A file under --dir, as output by `status`.
+#[derive(Debug, Serialize)]
+pub struct StatusRecord {
+    /// Path of the file relative to --dir.
+    pub path: PathBuf,
+    pub state: State,
+    /// SHA-256 of the working file, or null if it does not exist.
+    pub sha256: Option<String>,
+    /// Id of the newest backup, or null if there is none.
+    pub entry: Option<String>,
+    /// Time of the newest backup, or null if there is none.
+    pub time: Option<DateTime<Utc>>,
+    /// SHA-256 of the newest backup, or null if there is none.
+    pub entry_sha256: Option<String>,
+}
+
+/// CSV columns for `status`.
+pub static STATUS_CSV_COLUMNS: [&str; 6] =
+    ["path", "state", "sha256", "entry", "time", "entry_sha256"];
+
+/// The fields of [`StatusRecord`] for `--help`.
+pub static STATUS_SCHEMA: &str = "\
+Fields with --format json, ndjson or csv, one record per file:
+  path          string  file relative to --dir
+  state         string  unchanged, modified, deleted or never-recorded
+  sha256        string  SHA-256 of the working file; null if it does not exist
+  entry         string  id of the newest backup; null if there is none
+  time          string  time of the newest backup as RFC 3339 in UTC; null if
+                        there is none
+  entry_sha256  string  SHA-256 of the newest backup; null if there is none";
+
+/// A kind of problem found by `fsck`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Check {
+    /// A history folder or `entries.json` could not be read or parsed.
+    Unreadable,
+    /// An `entries.json` has a version this tool does not know.
+    UnknownVersion,
+    /// An entry's backup file does not exist. Repaired by dropping the entry.
+    MissingBackup,
+    /// A file in a history folder that no entry refers to.
+    Orphan,
+    /// Several history folders in one root record the same resource.
+    Duplicate,
+    /// An entry's timestamp lies in the future.
+    FutureTimestamp,
+}
+
+impl Check {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Check::Unreadable => "unreadable",
+            Check::UnknownVersion => "unknown-version",
+            Check::MissingBackup => "missing-backup",
+            Check::Orphan => "orphan",
+            Check::Duplicate => "duplicate",
+            Check::FutureTimestamp => "future-timestamp",
+        }
+    }
+}
+
+/// A problem in the history store, as output by `fsck`.
+#[derive(Debug, Serialize)]
+pub struct FsckRecord {
+    pub check: Check,
+    /// The file or folder with the problem.
+    pub path: PathBuf,
+    /// Human readable description.
+    pub detail: String,
+    /// Whether `--repair` fixed the problem.
+    pub repaired: bool,
+}
+
+/// CSV columns for `fsck`.
+pub static FSCK_CSV_COLUMNS: [&str; 4] = ["check", "path", "detail", "repaired"];
+
+/// The fields of [`FsckRecord`] for `--help`.
+pub static FSCK_SCHEMA: &str = "\
+Fields with --format json, ndjson or csv, one record per problem:
+  check     string   unreadable, unknown-version, missing-backup, orphan,
+                     duplicate or future-timestamp
+  path      string   the file or folder with the problem
+  detail    string   human readable description
+  repaired  boolean  whether --repair fixed the problem";
+
+/// History usage of a workspace, directory or file, as output by `du`.
+#[derive(Debug, Serialize)]
+pub struct DuRecord {
+    /// The workspace, directory or file, relative to --dir unless `--global`.
+    pub name: String,
+    /// Bytes used by the history folders.
+    pub size: u64,
+    /// Number of history folders.
+    pub folders: usize,
+    /// Number of entries in them.
+    pub entries: usize,
+    /// Oldest entry, or null if there are none.
+    pub oldest: Option<DateTime<Utc>>,
+    /// Most recent entry, or null if there are none.
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// CSV columns for `du`.
+pub static DU_CSV_COLUMNS: [&str; 6] = ["name", "size", "folders", "entries", "oldest", "newest"];
+
+/// The fields of [`DuRecord`] for `--help`.
+pub static DU_SCHEMA: &str = "\
+Fields with --format json, ndjson or csv, one record per row:
+  name     string   the workspace, directory or file, relative to --dir
+                    unless --global
+  size     integer  bytes used by the history folders
+  folders  integer  number of history folders
+  entries  integer  number of entries in them
+  oldest   string   oldest entry as RFC 3339 in UTC; null if none
+  newest   string   most recent entry as RFC 3339 in UTC; null if none";
+
+/// Help shown with `--help` rather than `-h`: the given sections, such as a
+/// command's usual help and its output fields.
+pub fn long_help(sections: &[&str]) -> String {
+    sections.join("\n\n")
+}
+
+/// Print records as JSON or NDJSON.
+pub fn print_json<T: Serialize>(records: &[T], format: Format) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    if format == Format::Ndjson {
+        for record in records {
+            serde_json::to_writer(&mut out, record)?;
+            writeln!(out)?;
+        }
+    } else {
+        serde_json::to_writer_pretty(&mut out, records)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Print records as CSV with the given columns. Nulls become empty cells and
+/// nested values are written as JSON.
+pub fn print_csv<T: Serialize>(columns: &[&str], records: &[T]) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{}", columns.join(","))?;
+    for record in records {
+        let record = serde_json::to_value(record)?;
+        let cells: Vec<_> = columns
+            .iter()
+            .map(|column| match record.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => csv_escape(s),
+                Some(other) => csv_escape(&other.to_string()),
+            })
+            .collect();
+        writeln!(out, "{}", cells.join(","))?;
+    }
+    Ok(())
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas_describe_every_column() {
+        for (schema, columns) in [
+            (LIST_SCHEMA, &LIST_CSV_COLUMNS[..]),
+            (LOG_SCHEMA, &LOG_CSV_COLUMNS[..]),
+            (STATUS_SCHEMA, &STATUS_CSV_COLUMNS[..]),
+            (FSCK_SCHEMA, &FSCK_CSV_COLUMNS[..]),
+            (DU_SCHEMA, &DU_CSV_COLUMNS[..]),
+        ] {
+            for column in columns {
+                let line = format!("\n  {} ", column);
+                let nested = format!("\n    {} ", column);
+                assert!(
+                    schema.contains(&line) || schema.contains(&nested),
+                    "{} is not described",
+                    column
+                );
+            }
+        }
+    }
+}
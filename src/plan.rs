@@ -0,0 +1,423 @@
+//! The plan built by every command that writes files, so it can be reviewed
+//! before anything is touched.
+
+use chrono::{DateTime, Local, Utc};
+use eyre::{eyre, Context, Result};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::diff;
+use crate::journal::{Journal, UNDO};
+use crate::prompt::{self, Answer};
+
+/// Where the new content of a target comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A history entry with its timestamp and backup file.
+    Entry(DateTime<Utc>, PathBuf),
+    /// The current copy of a file, e.g. when a snapshot has no history.
+    Current(PathBuf),
+    /// A copy saved in the journal before it was overwritten.
+    Journal(PathBuf),
+    /// Nothing: the target is removed.
+    Remove,
+}
+
+impl Source {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Source::Entry(_, path) | Source::Current(path) | Source::Journal(path) => Some(path),
+            Source::Remove => None,
+        }
+    }
+}
+
+/// Write one file, or remove it.
+#[derive(Debug)]
+pub struct Action {
+    /// Name shown for the target.
+    pub name: String,
+    pub source: Source,
+    pub target: PathBuf,
+    /// Size of the new content in bytes.
+    pub bytes: u64,
+    pub target_exists: bool,
+    /// Whether the target's content differs from the new content.
+    pub differs: bool,
+    /// Lines changed in the target, `None` for binary files.
+    pub stat: Option<diff::Stat>,
+    /// Whether the target holds changes that are in no backup, see
+    /// [`TrackedFile::has_unrecorded_changes`](crate::history::TrackedFile::has_unrecorded_changes).
+    pub unrecorded: bool,
+}
+
+impl Action {
+    /// Plan replacing `target` with `source`, comparing their contents.
+    pub fn new(name: String, source: Source, target: PathBuf) -> Result<Self> {
+        let new = match source.path() {
+            Some(path) => std::fs::read(path)
+                .with_context(|| format!("Could not read {}", path.to_string_lossy()))?,
+            None => Vec::new(),
+        };
+        let old = if target.is_file() {
+            Some(
+                std::fs::read(&target)
+                    .with_context(|| format!("Could not read {}", target.to_string_lossy()))?,
+            )
+        } else {
+            None
+        };
+        let old_bytes = old.as_deref().unwrap_or_default();
+        let stat = if diff::is_binary(old_bytes) || diff::is_binary(&new) {
+            None
+        } else {
+            Some(diff::stat(
+                &String::from_utf8_lossy(old_bytes),
+                &String::from_utf8_lossy(&new),
+            ))
+        };
+        let differs = match source {
+            Source::Remove => old.is_some(),
+            _ => old.as_deref() != Some(&new[..]),
+        };
+        Ok(Action {
+            name,
+            bytes: new.len() as u64,
+            target_exists: old.is_some(),
+            differs,
+            stat,
+            unrecorded: false,
+            source,
+            target,
+        })
+    }
+
+    /// Describe where the content comes from, e.g. `C3.rs @ 2026-10-14 15:30:00`.
+    pub fn source_label(&self) -> String {
+        match &self.source {
+            Source::Entry(ts, backup) => format!(
+                "{} @ {}",
+                backup.file_name().unwrap_or_default().to_string_lossy(),
+                ts.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+            ),
+            Source::Current(_) => "current copy".to_string(),
+            Source::Journal(_) => "journal copy".to_string(),
+            Source::Remove => "(remove)".to_string(),
+        }
+    }
+
+    /// Write the source over the target, creating missing parent directories,
+    /// or remove the target. See [`atomic::write`] for how the target is
+    /// replaced.
+    pub fn apply(&self) -> Result<()> {
+        let Some(source) = self.source.path() else {
+            if self.target_exists {
+                atomic::remove(&self.target)?;
+            }
+            return Ok(());
+        };
+        if let Some(parent) = self.target.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Could not create directory {}", parent.to_string_lossy())
+            })?;
+        }
+        let content = std::fs::read(source)
+            .with_context(|| format!("Could not read {}", source.to_string_lossy()))?;
+        atomic::write(&self.target, &content)
+    }
+}
+
+/// The files a command is going to write.
+#[derive(Debug)]
+pub struct Plan {
+    /// What the command does, e.g. `restore` or `snapshot`.
+    pub operation: String,
+    pub actions: Vec<Action>,
+    /// Remarks printed after the actions, e.g. files that were skipped.
+    pub notes: Vec<String>,
+}
+
+impl Plan {
+    pub fn new(operation: &str) -> Self {
+        Plan {
+            operation: operation.to_string(),
+            actions: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Drop the actions whose target lies outside `dir` once `..` and
+    /// symlinks are resolved, listing them in the notes, or fail if that
+    /// leaves nothing to do. Targets come from the URIs in `entries.json`, so
+    /// a crafted or corrupted history could otherwise make a command write
+    /// anywhere.
+    pub fn confine(&mut self, dir: &Path) -> Result<()> {
+        let dir = canonical(dir)?;
+        let mut rejected = Vec::new();
+        let mut kept = Vec::new();
+        for action in self.actions.drain(..) {
+            if canonical(&action.target)?.starts_with(&dir) {
+                kept.push(action);
+            } else {
+                rejected.push(action);
+            }
+        }
+        self.actions = kept;
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        let mut lines = Vec::new();
+        for action in rejected {
+            let source = action.source.path().unwrap_or(Path::new(""));
+            lines.push(format!(
+                "  {}  <- {}",
+                canonical(&action.target)?.to_string_lossy(),
+                source.to_string_lossy()
+            ));
+        }
+        if self.actions.is_empty() {
+            return Err(eyre!(
+                "Every file resolves to outside {}, pass --allow-outside to write them anyway:\n{}",
+                dir.to_string_lossy(),
+                lines.join("\n")
+            ));
+        }
+        self.notes.push(format!(
+            "Outside {}, skipped (pass --allow-outside to write them anyway):",
+            dir.to_string_lossy()
+        ));
+        self.notes.extend(lines);
+        Ok(())
+    }
+
+    /// Refuse to go ahead if any target holds changes that are not in its
+    /// history and would be lost, unless `force` is set.
+    pub fn check_unrecorded(&self, force: bool) -> Result<()> {
+        let unrecorded: Vec<_> = self
+            .actions
+            .iter()
+            .filter(|a| a.unrecorded && a.differs)
+            .map(|a| a.name.as_str())
+            .collect();
+        if force || unrecorded.is_empty() {
+            return Ok(());
+        }
+        Err(eyre!(
+            "Changed in ways none of their backups hold: {}\n\
+             Pass --force to {} anyway, the current content is saved to the journal \
+             and `tardis undo` brings it back",
+            unrecorded.join(", "),
+            self.operation
+        ))
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Plan for {}: {} file{}",
+            self.operation,
+            self.actions.len(),
+            if self.actions.len() == 1 { "" } else { "s" }
+        );
+        let width = self.actions.iter().map(|a| a.name.len()).max().unwrap_or(0);
+        for action in &self.actions {
+            let state = match (action.target_exists, action.differs) {
+                (false, _) => "new",
+                (true, true) if action.unrecorded => "unrecorded",
+                (true, true) => "differs",
+                (true, false) => "same",
+            };
+            let stat = match action.stat {
+                Some(stat) if action.differs => {
+                    format!("+{} -{}", stat.insertions, stat.deletions)
+                }
+                Some(_) => String::new(),
+                None => "binary".to_string(),
+            };
+            let line = format!(
+                "  {:width$}  <- {:<30}  {:>10}  {:<10}  {}",
+                action.name,
+                action.source_label(),
+                format!("{} bytes", action.bytes),
+                state,
+                stat,
+            );
+            println!("{}", line.trim_end());
+        }
+        for note in &self.notes {
+            println!("{}", note);
+        }
+    }
+
+    /// Carry out every action, saving the current content of each target to
+    /// the journal first. Returns the id of the journaled operation.
+    pub fn apply(&self, journal: &Journal) -> Result<String> {
+        let mut record = journal.start(&self.operation)?;
+        for action in &self.actions {
+            record.save(&action.target)?;
+            action.apply()?;
+            record.wrote(&action.target)?;
+        }
+        let id = record.finish()?;
+        print_written(&self.operation, self.actions.len(), &id);
+        Ok(id)
+    }
+
+    /// Ask before carrying out each action, journaling like [`Plan::apply`].
+    pub fn apply_interactive(&self, journal: &Journal) -> Result<String> {
+        let mut record = journal.start(&self.operation)?;
+        let mut ask = true;
+        let mut written = 0;
+        for action in &self.actions {
+            if ask {
+                let question = format!("Write {} from {}", action.name, action.source_label());
+                match prompt::ask(&question)? {
+                    Answer::Yes => {}
+                    Answer::No => continue,
+                    Answer::All => ask = false,
+                    Answer::Quit => break,
+                }
+            }
+            record.save(&action.target)?;
+            action.apply()?;
+            record.wrote(&action.target)?;
+            written += 1;
+        }
+        let id = record.finish()?;
+        print_written(&self.operation, written, &id);
+        Ok(id)
+    }
+}
+
+/// `path` with symlinks and `..` resolved, as far as it exists.
+fn canonical(path: &Path) -> Result<PathBuf> {
+    let path = crate::normalize(atomic::resolve(path)?);
+    let mut existing = path.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return Ok(missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name)));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return Ok(path.clone()),
+        }
+    }
+}
+
+/// Report the write and how to take it back. A plain `tardis undo` skips
+/// undo operations, so those need their id.
+fn print_written(operation: &str, count: usize, id: &str) {
+    let undo = match operation == UNDO {
+        true => format!("tardis undo --id {}", id),
+        false => "tardis undo".to_string(),
+    };
+    println!(
+        "Wrote {} file{}, undo with `{}`",
+        count,
+        if count == 1 { "" } else { "s" },
+        undo
+    );
+}
+
+/// Print the plan and decide whether to carry it out: never for a dry run,
+/// without asking if `yes` is set, otherwise after confirmation on stdin.
+pub fn confirm(plan: &Plan, dry_run: bool, yes: bool) -> Result<bool> {
+    plan.print();
+    if plan.actions.is_empty() {
+        return Ok(false);
+    }
+    proceed(&plan.operation, dry_run, yes)
+}
+
+/// Decide whether to carry out an operation whose plan was printed, like
+/// [`confirm`].
+pub fn proceed(operation: &str, dry_run: bool, yes: bool) -> Result<bool> {
+    if dry_run {
+        return Ok(false);
+    }
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "Refusing to write files without confirmation, pass --yes to proceed"
+        ));
+    }
+    eprint!("Proceed with {}? [y/N] ", operation);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(target: PathBuf) -> Action {
+        Action {
+            name: target.to_string_lossy().into_owned(),
+            source: Source::Remove,
+            target,
+            bytes: 0,
+            target_exists: true,
+            differs: true,
+            stat: None,
+            unrecorded: false,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_resolves_dots_and_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let base = tempfile::tempdir().unwrap();
+        let base = base.path();
+        let proj = base.join("proj");
+        std::fs::create_dir_all(proj.join("src")).unwrap();
+        std::fs::create_dir_all(base.join("outside")).unwrap();
+        std::fs::write(proj.join("src/a.rs"), "a").unwrap();
+        std::fs::write(base.join("outside/x.rs"), "x").unwrap();
+        symlink("../outside", proj.join("dir_link")).unwrap();
+        symlink("../outside/x.rs", proj.join("file_link")).unwrap();
+        symlink("src/a.rs", proj.join("inner_link")).unwrap();
+
+        let mut plan = Plan::new("restore");
+        for target in [
+            "src/a.rs",
+            "src/../../outside/x.rs",
+            "dir_link/x.rs",
+            "file_link",
+            "inner_link",
+            "missing/../src/b.rs",
+        ] {
+            plan.actions.push(action(proj.join(target)));
+        }
+        let result = plan.confine(&proj.join("src/.."));
+        let mut outside = Plan::new("restore");
+        outside.actions.push(action(proj.join("dir_link/x.rs")));
+        let all_rejected = outside.confine(&proj);
+        result.unwrap();
+        assert!(all_rejected.is_err());
+
+        let kept: Vec<_> = plan.actions.iter().map(|a| a.target.clone()).collect();
+        assert_eq!(
+            kept,
+            [
+                proj.join("src/a.rs"),
+                proj.join("inner_link"),
+                proj.join("missing/../src/b.rs")
+            ]
+        );
+        // A heading and one line per rejected target.
+        assert_eq!(plan.notes.len(), 4);
+    }
+}
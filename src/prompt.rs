@@ -0,0 +1,33 @@
+use eyre::{eyre, Result};
+use std::io::{BufRead, Write};
+
+/// An answer to a per-file question.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// Yes to this and every following question.
+    All,
+    Quit,
+}
+
+/// Ask a `[y,n,a,q]` question on stderr and read the answer from stdin,
+/// asking again until it is understood.
+pub fn ask(question: &str) -> Result<Answer> {
+    let stdin = std::io::stdin();
+    loop {
+        eprint!("{} [y,n,a,q]? ", question);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Err(eyre!("No answer, stdin is closed"));
+        }
+        match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Answer::Yes),
+            "n" | "no" => return Ok(Answer::No),
+            "a" | "all" => return Ok(Answer::All),
+            "q" | "quit" => return Ok(Answer::Quit),
+            _ => eprintln!("y - yes, n - no, a - this and all remaining, q - quit"),
+        }
+    }
+}
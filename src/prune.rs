@@ -0,0 +1,367 @@
+//! Thinning out the history store, like the retention policies of backup
+//! tools.
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use eyre::{eyre, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::history::CodeHistoryFile;
+
+/// Which entries to keep. An entry is kept if any rule keeps it; with no
+/// retention rule at all every entry is kept. The rules count the entries of
+/// a file across the history folders of every flavor.
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Keep the newest N entries of each file.
+    pub keep_last: Option<usize>,
+    /// Keep every entry newer than this.
+    pub keep_within: Option<TimeDelta>,
+    /// Keep the newest entry of each of the last N hours that have one.
+    pub hourly: Option<usize>,
+    /// Keep the newest entry of each of the last N days that have one.
+    pub daily: Option<usize>,
+    /// Keep the newest entry of each of the last N weeks that have one.
+    pub weekly: Option<usize>,
+    /// Drop the history of files that are not below any of these. They
+    /// must be canonical, the files' paths are resolved to compare them.
+    /// Resources that are not files, such as untitled buffers, are left to
+    /// the retention rules.
+    pub roots: Vec<PathBuf>,
+}
+
+impl Policy {
+    fn has_retention(&self) -> bool {
+        self.keep_last.is_some()
+            || self.keep_within.is_some()
+            || self.hourly.is_some()
+            || self.daily.is_some()
+            || self.weekly.is_some()
+    }
+
+    /// Indices of the entries to keep, given the timestamps of all entries
+    /// of one file. Hours, days and weeks go by the time zone of `now`.
+    fn keep<Tz: TimeZone>(
+        &self,
+        timestamps: &[DateTime<Utc>],
+        now: &DateTime<Tz>,
+    ) -> BTreeSet<usize>
+    where
+        Tz::Offset: Display,
+    {
+        if !self.has_retention() {
+            return (0..timestamps.len()).collect();
+        }
+        let mut newest_first: Vec<_> = (0..timestamps.len()).collect();
+        newest_first.sort_by_key(|&i| std::cmp::Reverse(timestamps[i]));
+
+        let mut keep = BTreeSet::new();
+        if let Some(n) = self.keep_last {
+            keep.extend(newest_first.iter().take(n));
+        }
+        if let Some(within) = self.keep_within {
+            // A duration reaching back before the earliest representable
+            // time keeps everything.
+            let since = now
+                .clone()
+                .checked_sub_signed(within)
+                .map(|since| since.with_timezone(&Utc));
+            keep.extend(
+                newest_first
+                    .iter()
+                    .filter(|&&i| since.is_none_or(|since| timestamps[i] >= since)),
+            );
+        }
+        for (count, bucket) in [
+            (self.hourly, "%Y-%m-%d %H"),
+            (self.daily, "%Y-%m-%d"),
+            (self.weekly, "%G-W%V"),
+        ] {
+            let Some(count) = count else {
+                continue;
+            };
+            let mut buckets = BTreeSet::new();
+            for &i in &newest_first {
+                let key = timestamps[i]
+                    .with_timezone(&now.timezone())
+                    .format(bucket)
+                    .to_string();
+                if buckets.len() < count && buckets.insert(key) {
+                    keep.insert(i);
+                }
+            }
+        }
+        keep
+    }
+}
+
+/// What happens to one history folder.
+#[derive(Debug)]
+pub struct Prune<'a> {
+    pub file: &'a CodeHistoryFile,
+    /// Indices of the entries kept. With none left the folder is removed.
+    pub keep: BTreeSet<usize>,
+    /// Backups of dropped entries and files no entry refers to.
+    pub delete: Vec<PathBuf>,
+    /// Total size of `delete` in bytes.
+    pub bytes: u64,
+}
+
+/// Apply the policy to every history folder, returning those that change.
+pub fn plan<'a, Tz: TimeZone>(
+    files: &'a [CodeHistoryFile],
+    policy: &Policy,
+    now: &DateTime<Tz>,
+) -> Result<Vec<Prune<'a>>>
+where
+    Tz::Offset: Display,
+{
+    if !policy.has_retention() && policy.roots.is_empty() {
+        return Err(eyre!(
+            "Nothing to prune, pass a retention rule such as --keep-last or --daily, or --root"
+        ));
+    }
+    let outside: Vec<_> = files
+        .iter()
+        .map(|file| {
+            file.is_file()
+                && !policy.roots.is_empty()
+                && !below(&file.current_file(), &policy.roots)
+        })
+        .collect();
+    let kept_files = files
+        .iter()
+        .zip(&outside)
+        .filter(|(file, &outside)| file.is_file() && !outside);
+    if !policy.roots.is_empty() && kept_files.count() == 0 {
+        return Err(eyre!(
+            "No history is below {}, which would drop all of it",
+            policy
+                .roots
+                .iter()
+                .map(|root| root.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let mut keeps = vec![BTreeSet::new(); files.len()];
+    for group in by_file(files) {
+        if outside[group[0]] {
+            continue;
+        }
+        let entries: Vec<_> = group
+            .iter()
+            .flat_map(|&f| (0..files[f].info.entries.len()).map(move |i| (f, i)))
+            .collect();
+        let timestamps: Vec<_> = entries
+            .iter()
+            .map(|&(f, i)| files[f].info.entries[i].timestamp)
+            .collect();
+        for k in policy.keep(&timestamps, now) {
+            let (f, i) = entries[k];
+            keeps[f].insert(i);
+        }
+    }
+
+    let mut prunes = Vec::new();
+    for (file, keep) in files.iter().zip(keeps) {
+        let kept: BTreeSet<_> = keep
+            .iter()
+            .map(|&i| file.dir.join(&file.info.entries[i].id))
+            .collect();
+
+        let mut delete = Vec::new();
+        let mut bytes = 0;
+        for e in std::fs::read_dir(&file.dir)
+            .with_context(|| format!("Could not read {}", file.dir.to_string_lossy()))?
+        {
+            let e = e?;
+            let path = e.path();
+            if e.file_type()?.is_file() && !path.ends_with("entries.json") && !kept.contains(&path)
+            {
+                bytes += e.metadata()?.len();
+                delete.push(path);
+            }
+        }
+        delete.sort();
+        if keep.len() < file.info.entries.len() || !delete.is_empty() {
+            prunes.push(Prune {
+                file,
+                keep,
+                delete,
+                bytes,
+            });
+        }
+    }
+    Ok(prunes)
+}
+
+/// Indices of the history folders, grouped by the file they record. Other
+/// resources, such as untitled buffers, each form a group of their own.
+fn by_file(files: &[CodeHistoryFile]) -> Vec<Vec<usize>> {
+    let mut groups = Vec::new();
+    let mut by_path: BTreeMap<_, Vec<usize>> = BTreeMap::new();
+    for (f, file) in files.iter().enumerate() {
+        match file.is_file() {
+            true => by_path
+                .entry((file.remote_authority(), file.current_file()))
+                .or_default()
+                .push(f),
+            false => groups.push(vec![f]),
+        }
+    }
+    groups.extend(by_path.into_values());
+    groups
+}
+
+/// Whether `path`, or the file it resolves to, is below one of `roots`.
+fn below(path: &Path, roots: &[PathBuf]) -> bool {
+    let resolved = path.canonicalize().ok();
+    roots.iter().any(|root| {
+        path.starts_with(root) || resolved.as_ref().is_some_and(|path| path.starts_with(root))
+    })
+}
+
+impl Prune<'_> {
+    /// Number of entries dropped from `entries.json`.
+    pub fn dropped(&self) -> usize {
+        self.file.info.entries.len() - self.keep.len()
+    }
+
+    /// Rewrite `entries.json` first and only then delete backups, so an
+    /// interruption leaves stray files rather than entries without a backup.
+    /// A folder with no entries left is removed entirely.
+    pub fn apply(&self) -> Result<()> {
+        let entries_file = self.file.dir.join("entries.json");
+        if self.keep.is_empty() {
+            std::fs::remove_file(&entries_file)
+                .with_context(|| format!("Could not remove {}", entries_file.to_string_lossy()))?;
+        } else if self.dropped() > 0 {
+            self.file.write_entries(&self.keep)?;
+        }
+        for path in &self.delete {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove {}", path.to_string_lossy()))?;
+        }
+        if self.keep.is_empty() {
+            // Fails if something other than backups is left, which is kept.
+            let _ = std::fs::remove_dir(&self.file.dir);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::CodeHistoryEntry;
+    use chrono::FixedOffset;
+
+    /// An entry at the given UTC time.
+    fn entry(m: u32, d: u32, h: u32, min: u32) -> CodeHistoryEntry {
+        CodeHistoryEntry {
+            id: PathBuf::from(format!("{:02}{:02}{:02}{:02}.rs", m, d, h, min)),
+            timestamp: Utc.with_ymd_and_hms(2026, m, d, h, min, 0).unwrap(),
+            extra: Default::default(),
+        }
+    }
+
+    /// Keep entries as of 18:00 UTC on 2026-10-16, in a zone two hours
+    /// ahead of UTC, like Central European Summer Time.
+    fn kept(policy: &Policy, entries: &[CodeHistoryEntry]) -> Vec<String> {
+        let zone = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+        let now = Utc
+            .with_ymd_and_hms(2026, 10, 16, 18, 0, 0)
+            .unwrap()
+            .with_timezone(&zone);
+        let timestamps: Vec<_> = entries.iter().map(|e| e.timestamp).collect();
+        policy
+            .keep(&timestamps, &now)
+            .into_iter()
+            .map(|i| entries[i].id.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn hourly() {
+        let entries = [
+            entry(10, 16, 10, 5),
+            entry(10, 16, 10, 40),
+            entry(10, 16, 11, 10),
+            entry(10, 16, 12, 30),
+            entry(10, 16, 12, 50),
+        ];
+        let policy = Policy {
+            hourly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries), ["10161110.rs", "10161250.rs"]);
+    }
+
+    #[test]
+    fn daily_by_local_day() {
+        // 23:30 UTC on the 15th is already the 16th two hours ahead.
+        let entries = [
+            entry(10, 14, 8, 0),
+            entry(10, 14, 20, 0),
+            entry(10, 15, 9, 0),
+            entry(10, 15, 23, 30),
+            entry(10, 16, 9, 0),
+        ];
+        let policy = Policy {
+            daily: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries), ["10150900.rs", "10160900.rs"]);
+    }
+
+    #[test]
+    fn weekly_by_iso_week() {
+        // Sunday the 4th ends week 40, Monday the 12th starts week 42.
+        let entries = [
+            entry(10, 4, 12, 0),
+            entry(10, 7, 12, 0),
+            entry(10, 12, 12, 0),
+            entry(10, 16, 12, 0),
+        ];
+        let policy = Policy {
+            weekly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries), ["10071200.rs", "10161200.rs"]);
+    }
+
+    #[test]
+    fn rules_add_up() {
+        let entries = [
+            entry(10, 14, 8, 0),
+            entry(10, 15, 9, 0),
+            entry(10, 16, 9, 0),
+            entry(10, 16, 17, 0),
+        ];
+        let policy = Policy {
+            keep_last: Some(1),
+            daily: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries), ["10150900.rs", "10161700.rs"]);
+        let policy = Policy {
+            keep_last: Some(1),
+            keep_within: TimeDelta::try_hours(12),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries), ["10160900.rs", "10161700.rs"]);
+    }
+
+    #[test]
+    fn keep_everything() {
+        let entries = [entry(10, 14, 8, 0), entry(10, 16, 9, 0)];
+        assert_eq!(kept(&Policy::default(), &entries).len(), 2);
+        let policy = Policy {
+            keep_within: TimeDelta::try_weeks(100_000_000),
+            ..Default::default()
+        };
+        assert_eq!(kept(&policy, &entries).len(), 2);
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::history::TrackedFile;
+use crate::plan::{Action, Plan, Source};
+use crate::select::Selector;
+
+/// Plan restoring every tracked file to its newest backup at or before `to`.
+///
+/// Files without a backup at that time are left alone and listed in the
+/// plan's notes, as are files that already match when `only_changed` is set.
+/// `written` holds what tardis last wrote to each file, see
+/// [`Journal::last_written`](crate::journal::Journal::last_written).
+pub fn plan(
+    tracked: &[TrackedFile],
+    current_dir: &Path,
+    to: DateTime<Utc>,
+    only_changed: bool,
+    written: &HashMap<PathBuf, String>,
+) -> Result<Plan> {
+    let mut plan = Plan::new("rollback");
+    let mut skipped = Vec::new();
+    let mut unchanged = 0;
+    for file in tracked {
+        let name = file.path.strip_prefix(current_dir)?.to_string_lossy();
+        let backups = file.backup_files();
+        let Some((ts, backup)) = Selector::At(to).select(&backups) else {
+            skipped.push(name);
+            continue;
+        };
+        let mut action = Action::new(
+            name.to_string(),
+            Source::Entry(*ts, backup.clone()),
+            file.path.clone(),
+        )?;
+        action.unrecorded = action.differs
+            && file.has_unrecorded_changes(written.get(&file.path).map(String::as_str))?;
+        if only_changed && !action.differs {
+            unchanged += 1;
+            continue;
+        }
+        plan.actions.push(action);
+    }
+
+    if unchanged > 0 {
+        plan.notes
+            .push(format!("{} files already match and are skipped", unchanged));
+    }
+    if !skipped.is_empty() {
+        plan.notes
+            .push("No history at that time, left as they are:".to_string());
+        plan.notes
+            .extend(skipped.iter().map(|name| format!("  {}", name)));
+    }
+    Ok(plan)
+}
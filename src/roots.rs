@@ -0,0 +1,155 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+static USER_HISTORY_DIR: &str = "User/History";
+static SERVER_HISTORY_DIR: &str = "data/User/History";
+
+/// The editors that keep a VS Code style local history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Flavor {
+    #[serde(rename = "code")]
+    Code,
+    #[serde(rename = "code-insiders")]
+    CodeInsiders,
+    #[serde(rename = "vscodium")]
+    VSCodium,
+    #[serde(rename = "code-oss")]
+    CodeOss,
+    #[serde(rename = "cursor")]
+    Cursor,
+    #[serde(rename = "windsurf")]
+    Windsurf,
+}
+
+impl Flavor {
+    pub const ALL: [Flavor; 6] = [
+        Flavor::Code,
+        Flavor::CodeInsiders,
+        Flavor::VSCodium,
+        Flavor::CodeOss,
+        Flavor::Cursor,
+        Flavor::Windsurf,
+    ];
+
+    /// Name of the application folder inside the user's config directory.
+    pub fn app_dir(self) -> &'static str {
+        match self {
+            Flavor::Code => "Code",
+            Flavor::CodeInsiders => "Code - Insiders",
+            Flavor::VSCodium => "VSCodium",
+            Flavor::CodeOss => "Code - OSS",
+            Flavor::Cursor => "Cursor",
+            Flavor::Windsurf => "Windsurf",
+        }
+    }
+
+    /// Name of the folder in the home directory used by the flavor's remote
+    /// server (Remote-SSH, WSL, dev containers).
+    pub fn server_dir(self) -> &'static str {
+        match self {
+            Flavor::Code => ".vscode-server",
+            Flavor::CodeInsiders => ".vscode-server-insiders",
+            Flavor::VSCodium => ".vscodium-server",
+            Flavor::CodeOss => ".vscode-server-oss",
+            Flavor::Cursor => ".cursor-server",
+            Flavor::Windsurf => ".windsurf-server",
+        }
+    }
+}
+
+impl fmt::Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.app_dir())
+    }
+}
+
+/// A `User/History` directory and the editor that writes to it, if known.
+#[derive(Debug, Clone)]
+pub struct HistoryRoot {
+    pub flavor: Option<Flavor>,
+    pub path: PathBuf,
+}
+
+impl HistoryRoot {
+    /// A history directory given on the command line.
+    ///
+    /// The flavor is guessed from the path so that e.g. a copied
+    /// `.../Code - Insiders/User/History` is still labelled correctly.
+    pub fn explicit(path: PathBuf) -> Result<Self> {
+        if !path.is_dir() {
+            return Err(eyre!("History directory {:?} does not exist", path));
+        }
+        let flavor = Flavor::ALL.into_iter().find(|flavor| {
+            path.components()
+                .any(|c| c.as_os_str() == flavor.app_dir() || c.as_os_str() == flavor.server_dir())
+        });
+        Ok(HistoryRoot { flavor, path })
+    }
+
+    /// The history directory of a VS Code `--user-data-dir`.
+    pub fn user_data_dir(path: &Path) -> Result<Self> {
+        Self::explicit(path.join(USER_HISTORY_DIR))
+    }
+}
+
+/// Directories that may contain per-application config folders.
+///
+/// `$XDG_CONFIG_HOME` and `~/.config` cover Linux, the other two cover macOS
+/// and Windows. `$XDG_CONFIG_HOME` belongs to the current user, so it is only
+/// used when `home` is theirs.
+fn config_dirs(home: &Path, own_home: bool) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|_| own_home)
+        .map(PathBuf::from)
+    {
+        // The XDG spec says relative values are invalid and must be ignored.
+        if xdg.is_absolute() {
+            dirs.push(xdg);
+        }
+    }
+    dirs.push(home.join(".config"));
+    dirs.push(home.join("Library/Application Support"));
+    dirs.push(home.join("AppData/Roaming"));
+    dirs
+}
+
+/// Find every history directory that exists for the given home directory,
+/// both for desktop installs and for servers started by remote sessions.
+///
+/// The same directory is only reported once, even if it is reachable through
+/// several config locations (e.g. `$XDG_CONFIG_HOME` pointing at `~/.config`).
+/// `own_home` tells whether `home` is the current user's, see [`config_dirs`].
+pub fn discover(home: &Path, own_home: bool) -> Vec<HistoryRoot> {
+    let mut roots: Vec<HistoryRoot> = Vec::new();
+    let mut seen = Vec::new();
+    let mut add = |flavor: Flavor, path: PathBuf| {
+        let Ok(canonical) = path.canonicalize() else {
+            return;
+        };
+        if canonical.is_dir() && !seen.contains(&canonical) {
+            seen.push(canonical);
+            roots.push(HistoryRoot {
+                flavor: Some(flavor),
+                path,
+            });
+        }
+    };
+    for config_dir in config_dirs(home, own_home) {
+        for flavor in Flavor::ALL {
+            add(
+                flavor,
+                config_dir.join(flavor.app_dir()).join(USER_HISTORY_DIR),
+            );
+        }
+    }
+    for flavor in Flavor::ALL {
+        add(
+            flavor,
+            home.join(flavor.server_dir()).join(SERVER_HISTORY_DIR),
+        );
+    }
+    roots
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::timeexpr;
+
+/// Short description of revision syntax, for `--help` output.
+pub static REV_HELP: &str = "REV is an index as shown by `log` (0 is the oldest), ~N for N \
+entries back from the latest, an entry id or a time, meaning the newest entry at or before it.";
+
+/// Which backup of a file to use, resolved against its entry timestamps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// The most recent entry.
+    Latest,
+    /// The entry at this position, counting from the oldest which is `0`.
+    Index(usize),
+    /// The entry with this id, which is also the backup's file name.
+    Entry(String),
+    /// The newest entry strictly before the time.
+    Before(DateTime<Utc>),
+    /// The newest entry at or before the time.
+    At(DateTime<Utc>),
+    /// The entry `n` steps back from the most recent one, which is `0`.
+    NthBack(usize),
+}
+
+impl Selector {
+    /// Parse a revision as given on the command line, see [`REV_HELP`].
+    pub fn from_rev(rev: &str) -> Result<Selector> {
+        if let Ok(index) = rev.parse() {
+            return Ok(Selector::Index(index));
+        }
+        if let Some(n) = rev.strip_prefix('~').and_then(|n| n.parse().ok()) {
+            return Ok(Selector::NthBack(n));
+        }
+        match timeexpr::parse(rev) {
+            Ok(time) => Ok(Selector::At(time)),
+            Err(_) => Ok(Selector::Entry(rev.to_string())),
+        }
+    }
+
+    /// Pick the selected backup from `backups`, which must be sorted oldest
+    /// first as returned by `backup_files()`.
+    pub fn select<'a>(
+        &self,
+        backups: &'a [(DateTime<Utc>, PathBuf)],
+    ) -> Option<&'a (DateTime<Utc>, PathBuf)> {
+        match self {
+            Selector::Latest => backups.last(),
+            Selector::Index(index) => backups.get(*index),
+            Selector::Entry(id) => backups
+                .iter()
+                .find(|(_, backup)| backup.file_name().is_some_and(|name| name == id.as_str())),
+            Selector::Before(time) => backups.iter().rev().find(|(ts, _)| ts < time),
+            Selector::At(time) => backups.iter().rev().find(|(ts, _)| ts <= time),
+            Selector::NthBack(n) => backups.iter().rev().nth(*n),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Latest => write!(f, "latest entry"),
+            Selector::Index(index) => write!(f, "entry #{}", index),
+            Selector::Entry(id) => write!(f, "entry {}", id),
+            Selector::Before(time) => write!(f, "entry before {}", time),
+            Selector::At(time) => write!(f, "entry at {}", time),
+            Selector::NthBack(n) => write!(f, "entry {} back from the latest", n),
+        }
+    }
+}
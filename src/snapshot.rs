@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
+use std::path::Path;
+
+use crate::history::TrackedFile;
+use crate::plan::{Action, Plan, Source};
+use crate::select::Selector;
+
+/// Plan writing the newest backup at or before `at` of every tracked file
+/// into a mirrored tree below `out`.
+///
+/// Files without a backup at that time fall back to their current copy and
+/// are listed in the plan's notes.
+pub fn plan(
+    tracked: &[TrackedFile],
+    current_dir: &Path,
+    at: DateTime<Utc>,
+    out: &Path,
+) -> Result<Plan> {
+    if out.exists() && out.read_dir()?.next().is_some() {
+        return Err(eyre!(
+            "Output directory {} is not empty",
+            out.to_string_lossy()
+        ));
+    }
+
+    let mut plan = Plan::new("snapshot");
+    let mut fallbacks = Vec::new();
+    let mut missing = Vec::new();
+    for file in tracked {
+        let name = file.path.strip_prefix(current_dir)?;
+        let target = out.join(name);
+        let action = match Selector::At(at).select(&file.backup_files()) {
+            Some((ts, backup)) => Action::new(
+                target.to_string_lossy().into_owned(),
+                Source::Entry(*ts, backup.clone()),
+                target,
+            )?,
+            None if file.path.is_file() => {
+                fallbacks.push(name.to_string_lossy());
+                Action::new(
+                    target.to_string_lossy().into_owned(),
+                    Source::Current(file.path.clone()),
+                    target,
+                )?
+            }
+            None => {
+                missing.push(name.to_string_lossy());
+                continue;
+            }
+        };
+        plan.actions.push(action);
+    }
+
+    if !fallbacks.is_empty() {
+        plan.notes
+            .push("No history at that time, using the current file:".to_string());
+        plan.notes
+            .extend(fallbacks.iter().map(|name| format!("  {}", name)));
+    }
+    if !missing.is_empty() {
+        plan.notes
+            .push("No history at that time and no current file, skipped:".to_string());
+        plan.notes
+            .extend(missing.iter().map(|name| format!("  {}", name)));
+    }
+    Ok(plan)
+}
@@ -0,0 +1,92 @@
+use eyre::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::hash;
+use crate::history::TrackedFile;
+use crate::output::{State, StatusRecord};
+
+/// Compare every tracked file under `current_dir` to its newest backup by
+/// content hash. With `untracked`, files under `current_dir` that have no
+/// history at all are included as never recorded, unless they are hidden or
+/// ignored by `.gitignore` and similar files.
+pub fn status(
+    tracked: &[TrackedFile],
+    current_dir: &Path,
+    untracked: bool,
+) -> Result<Vec<StatusRecord>> {
+    let mut records = Vec::new();
+    for file in tracked {
+        let sha256 = match file.path.is_file() {
+            true => Some(hash::sha256_file(&file.path)?),
+            false => None,
+        };
+        let newest = file.backup_files().pop();
+        let entry_sha256 = match &newest {
+            Some((_, backup)) => Some(hash::sha256_file(backup)?),
+            None => None,
+        };
+        let state = match (&sha256, &entry_sha256) {
+            (None, _) => State::Deleted,
+            (Some(_), None) => State::NeverRecorded,
+            (Some(a), Some(b)) if a == b => State::Unchanged,
+            (Some(_), Some(_)) => State::Modified,
+        };
+        records.push(StatusRecord {
+            path: file.path.strip_prefix(current_dir)?.to_path_buf(),
+            state,
+            sha256,
+            entry: newest
+                .as_ref()
+                .and_then(|(_, backup)| backup.file_name())
+                .map(|name| name.to_string_lossy().into_owned()),
+            time: newest.map(|(ts, _)| ts),
+            entry_sha256,
+        });
+    }
+
+    if untracked {
+        let known: BTreeSet<&PathBuf> = tracked.iter().map(|file| &file.path).collect();
+        // Hidden and ignored files are skipped, so build output such as
+        // `target/` or `node_modules/` is not hashed.
+        let walk = ignore::WalkBuilder::new(current_dir)
+            .require_git(false)
+            .build();
+        for e in walk.filter_map(|e| e.ok()) {
+            let is_file = e.file_type().is_some_and(|t| t.is_file());
+            if !is_file || known.contains(&e.path().to_path_buf()) {
+                continue;
+            }
+            records.push(StatusRecord {
+                path: e.path().strip_prefix(current_dir)?.to_path_buf(),
+                state: State::NeverRecorded,
+                sha256: Some(hash::sha256_file(e.path())?),
+                entry: None,
+                time: None,
+                entry_sha256: None,
+            });
+        }
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untracked_skips_hidden_and_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["new.txt", "target/debug/a.o", ".hidden/h", "src/lib.rs"] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+
+        let records = status(&[], dir.path(), true).unwrap();
+        let paths: Vec<_> = records.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(paths, [Path::new("new.txt"), Path::new("src/lib.rs")]);
+    }
+}
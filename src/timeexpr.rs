@@ -0,0 +1,222 @@
+//! Parsing of the time expressions accepted by every time based option.
+//!
+//! Accepted forms, all case-insensitive:
+//!
+//! - `now`
+//! - RFC 3339, e.g. `2026-10-14T15:30:00Z` or `2026-10-14T15:30:00+02:00`
+//! - local date and time, e.g. `2026-10-14 15:30`, `2026-10-14T15:30:45` or
+//!   `2026-10-14` (midnight)
+//! - local time of day, e.g. `15:30` (today)
+//! - relative, e.g. `2h ago`, `90 minutes ago`, `3 days ago`
+//! - day names with an optional time, e.g. `today`, `yesterday 17:00`,
+//!   `friday`, `last friday 9:00`
+
+use chrono::{
+    DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc,
+    Weekday,
+};
+use eyre::{eyre, Result};
+
+/// Short description of the accepted forms, for `--help` output.
+pub static HELP: &str = "TIME can be RFC 3339 (2026-10-14T15:30:00Z), a local date and time \
+(2026-10-14 15:30), a time of day (15:30), relative (2h ago, 3 days ago) or a day \
+(today, yesterday 17:00, last friday).";
+
+/// Parse a time expression relative to the current local time.
+pub fn parse(input: &str) -> Result<DateTime<Utc>> {
+    parse_at(input, Local::now())
+}
+
+/// Parse a time expression relative to `now`, reading local dates and times
+/// in the time zone of `now`.
+pub fn parse_at<Tz: TimeZone>(input: &str, now: DateTime<Tz>) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input.trim()) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let input = input.trim().to_lowercase();
+    if input == "now" {
+        return Ok(now.with_timezone(&Utc));
+    }
+    let tz = now.timezone();
+    if let Some(time) = parse_local_datetime(&input) {
+        return to_utc(&tz, time, &input);
+    }
+    if let Some(ago) = input.strip_suffix(" ago") {
+        let delta = parse_duration(ago)?;
+        return now
+            .checked_sub_signed(delta)
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| eyre!("{:?} is too far in the past", input));
+    }
+    if let Some(time) = parse_time_of_day(&input) {
+        return to_utc(&tz, now.date_naive().and_time(time), &input);
+    }
+    if let Some(time) = parse_day(&input, now.date_naive()) {
+        return to_utc(&tz, time, &input);
+    }
+    Err(eyre!(
+        "Could not understand time {:?}. {}",
+        input.as_str(),
+        HELP
+    ))
+}
+
+/// Parse a length of time such as `2h`, `90 minutes` or `3 days`.
+pub fn parse_duration(input: &str) -> Result<TimeDelta> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (count, unit) = input.split_at(split);
+    let count: i64 = count
+        .parse()
+        .map_err(|_| eyre!("Expected a number in {:?}", input))?;
+    let seconds = match unit.trim() {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+        unit => return Err(eyre!("Unknown time unit {:?} in {:?}", unit, input)),
+    };
+    count
+        .checked_mul(seconds)
+        .and_then(TimeDelta::try_seconds)
+        .ok_or_else(|| eyre!("{:?} is too long", input))
+}
+
+/// Describe how long ago `time` was, e.g. `3 hours ago`.
+pub fn format_age(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - time).num_seconds();
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+    const UNITS: [(&str, i64); 6] = [
+        ("year", 365 * 24 * 60 * 60),
+        ("month", 30 * 24 * 60 * 60),
+        ("week", 7 * 24 * 60 * 60),
+        ("day", 24 * 60 * 60),
+        ("hour", 60 * 60),
+        ("minute", 60),
+    ];
+    for (unit, length) in UNITS {
+        let count = seconds / length;
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", count, unit, plural);
+        }
+    }
+    "just now".to_string()
+}
+
+fn parse_local_datetime(input: &str) -> Option<NaiveDateTime> {
+    const FORMATS: [&str; 4] = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dt%H:%M:%S",
+        "%Y-%m-%dt%H:%M",
+    ];
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+fn parse_time_of_day(input: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(input, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(input, "%H:%M"))
+        .ok()
+}
+
+/// `today`, `yesterday`, `friday` or `last friday`, each optionally followed
+/// by a time of day. A bare weekday is the most recent such day including
+/// today, `last` excludes today.
+fn parse_day(input: &str, today: NaiveDate) -> Option<NaiveDateTime> {
+    let (day, time) = match input.rsplit_once(' ') {
+        Some((day, time)) => match parse_time_of_day(time) {
+            Some(time) => (day, time),
+            None => (input, NaiveTime::MIN),
+        },
+        None => (input, NaiveTime::MIN),
+    };
+    let date = match day {
+        "today" => today,
+        "yesterday" => today.pred_opt()?,
+        _ => {
+            let (weekday, skip_today) = match day.strip_prefix("last ") {
+                Some(weekday) => (weekday.parse::<Weekday>().ok()?, true),
+                None => (day.parse::<Weekday>().ok()?, false),
+            };
+            let mut days_back =
+                (7 + today.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+            if days_back == 0 && skip_today {
+                days_back = 7;
+            }
+            today.checked_sub_signed(TimeDelta::try_days(days_back.into())?)?
+        }
+    };
+    Some(date.and_time(time))
+}
+
+fn to_utc<Tz: TimeZone>(tz: &Tz, time: NaiveDateTime, input: &str) -> Result<DateTime<Utc>> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| eyre!("{:?} does not exist in the local time zone", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{Europe::Berlin, Tz};
+
+    /// Local time in a zone with daylight saving time.
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Tz> {
+        Berlin.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+    #[test]
+    fn yesterday_with_time() {
+        let now = local(2026, 10, 16, 9, 0);
+        assert_eq!(
+            parse_at("yesterday 17:00", now).unwrap(),
+            local(2026, 10, 15, 17, 0)
+        );
+    }
+
+    #[test]
+    fn weekday_on_that_weekday() {
+        // 2026-10-16 is a Friday.
+        let now = local(2026, 10, 16, 9, 0);
+        assert_eq!(
+            parse_at("last friday", now).unwrap(),
+            local(2026, 10, 9, 0, 0)
+        );
+        assert_eq!(parse_at("friday", now).unwrap(), local(2026, 10, 16, 0, 0));
+    }
+
+    #[test]
+    fn relative() {
+        let now = local(2026, 10, 16, 9, 0);
+        assert_eq!(parse_at("2h ago", now).unwrap(), local(2026, 10, 16, 7, 0));
+        assert_eq!(
+            parse_at("90 minutes ago", now).unwrap(),
+            local(2026, 10, 16, 7, 30)
+        );
+    }
+
+    #[test]
+    fn dst_gap() {
+        // Clocks went from 02:00 to 03:00 on 2026-03-29.
+        let now = local(2026, 4, 1, 9, 0);
+        assert!(parse_at("2026-03-29 02:30", now).is_err());
+        assert!(parse_at("last sunday 2:30", now).is_err());
+        assert_eq!(
+            parse_at("last sunday 3:30", now).unwrap(),
+            local(2026, 3, 29, 3, 30)
+        );
+    }
+}
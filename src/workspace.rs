@@ -0,0 +1,26 @@
+//! Finding the project a file belongs to.
+
+use std::path::{Path, PathBuf};
+
+/// Files or directories that mark the root of a project.
+static MARKERS: [&str; 7] = [
+    ".git",
+    ".hg",
+    ".svn",
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+];
+
+/// The project `file` belongs to: the nearest directory above it with a
+/// marker such as `.git` or `Cargo.toml`, or else the file's own directory,
+/// e.g. for files that are deleted or on a remote host.
+pub fn root(file: &Path) -> PathBuf {
+    let parent = file.parent().unwrap_or(file);
+    parent
+        .ancestors()
+        .find(|dir| MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .unwrap_or(parent)
+        .to_path_buf()
+}